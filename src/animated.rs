@@ -0,0 +1,278 @@
+use crate::Error;
+use gif::{EncodingError, Repeat};
+use image::png::PngEncoder;
+use image::{ColorType, DynamicImage, RgbImage};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+//Single file formats that hold a whole animation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AnimatedFormat {
+    //256 colours per frame picked by quantising each frame
+    Gif,
+    //Animated PNG, full colour and lossless
+    Apng,
+}
+
+impl AnimatedFormat {
+    //Picks the format from the file extension, .gif for GIF and .png or .apng for APNG
+    pub fn from_path(path: &str) -> Option<AnimatedFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(AnimatedFormat::Gif),
+            "png" | "apng" => Some(AnimatedFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+enum Encoder {
+    //The GIF encoder needs the frame size, so it takes over the file on the first frame
+    Gif {
+        out: Option<BufWriter<File>>,
+        encoder: Option<gif::Encoder<BufWriter<File>>>,
+    },
+    //APNG needs the frame count before the first frame, so the compressed frames are kept
+    //until finish
+    Apng {
+        out: BufWriter<File>,
+        header: Vec<u8>,
+        frames: Vec<Vec<u8>>,
+    },
+}
+
+//Writes frames one at a time into an animated GIF or APNG that loops forever
+pub struct AnimatedWriter {
+    path: String,
+    encoder: Encoder,
+    width: u32,
+    height: u32,
+    delay_ms: u32,
+}
+
+impl AnimatedWriter {
+    pub fn create(path: &str, delay_ms: u32) -> Result<AnimatedWriter, Error> {
+        let format = AnimatedFormat::from_path(path).ok_or_else(|| {
+            Error::Options(format!(
+                "{}: animations can only be written as .gif, .png or .apng",
+                path
+            ))
+        })?;
+        let out = BufWriter::new(File::create(path).map_err(|e| Error::io(path, e))?);
+        let encoder = match format {
+            AnimatedFormat::Gif => Encoder::Gif {
+                out: Some(out),
+                encoder: None,
+            },
+            AnimatedFormat::Apng => Encoder::Apng {
+                out,
+                header: Vec::new(),
+                frames: Vec::new(),
+            },
+        };
+        Ok(AnimatedWriter {
+            path: String::from(path),
+            encoder,
+            width: 0,
+            height: 0,
+            delay_ms,
+        })
+    }
+
+    pub fn add_frame(&mut self, image: &RgbImage) -> Result<(), Error> {
+        let path = &self.path;
+        let error = |e: image::ImageError| Error::image(path, e);
+        if self.width == 0 {
+            self.width = image.width();
+            self.height = image.height();
+        } else if (image.width(), image.height()) != (self.width, self.height) {
+            return Err(Error::Options(format!(
+                "{}: every frame must be the same size",
+                path
+            )));
+        }
+
+        match &mut self.encoder {
+            Encoder::Gif { out, encoder } => {
+                let too_big = || {
+                    Error::Options(format!(
+                        "{}: GIF frames can be at most {} pixels across",
+                        path,
+                        u16::MAX
+                    ))
+                };
+                let width = u16::try_from(image.width()).map_err(|_| too_big())?;
+                let height = u16::try_from(image.height()).map_err(|_| too_big())?;
+                if let Some(out) = out.take() {
+                    let mut gif = gif::Encoder::new(out, width, height, &[])
+                        .map_err(|e| gif_error(path, e))?;
+                    gif.set_repeat(Repeat::Infinite)
+                        .map_err(|e| gif_error(path, e))?;
+                    *encoder = Some(gif);
+                }
+
+                let mut rgba = DynamicImage::ImageRgb8(image.clone())
+                    .into_rgba8()
+                    .into_raw();
+                let mut frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 1);
+                //GIF delays are in hundredths of a second
+                frame.delay = (self.delay_ms / 10).min(u16::MAX as u32) as u16;
+                match encoder {
+                    Some(encoder) => encoder.write_frame(&frame).map_err(|e| gif_error(path, e)),
+                    //Only left empty by a failed first frame
+                    None => Err(Error::io(
+                        path,
+                        std::io::Error::other("an earlier write failed"),
+                    )),
+                }
+            }
+            Encoder::Apng { header, frames, .. } => {
+                //Let the PNG encoder do the filtering and compression then lift out its chunks
+                let mut png = Vec::new();
+                PngEncoder::new(&mut png)
+                    .encode(image, image.width(), image.height(), ColorType::Rgb8)
+                    .map_err(error)?;
+                let mut data = Vec::new();
+                for (kind, body) in chunks(&png, path)? {
+                    match kind {
+                        b"IHDR" if header.is_empty() => header.extend_from_slice(body),
+                        b"IDAT" => data.extend_from_slice(body),
+                        _ => {}
+                    }
+                }
+                frames.push(data);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        let path = self.path;
+        let error = |e: std::io::Error| Error::io(&path, e);
+        match self.encoder {
+            //Taking the file back from the encoder writes the GIF trailer
+            Encoder::Gif {
+                encoder: Some(encoder),
+                ..
+            } => {
+                let mut out = encoder.into_inner().map_err(error)?;
+                out.flush().map_err(error)
+            }
+            Encoder::Gif { encoder: None, .. } => {
+                Err(Error::Options(format!("{}: no frames to write", path)))
+            }
+            Encoder::Apng {
+                mut out,
+                header,
+                frames,
+            } => {
+                if frames.is_empty() {
+                    return Err(Error::Options(format!("{}: no frames to write", path)));
+                }
+                out.write_all(b"\x89PNG\r\n\x1a\n").map_err(error)?;
+                write_chunk(&mut out, b"IHDR", &header).map_err(error)?;
+
+                //acTL: frame count and 0 plays for looping forever
+                let mut control = Vec::new();
+                control.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+                control.extend_from_slice(&0u32.to_be_bytes());
+                write_chunk(&mut out, b"acTL", &control).map_err(error)?;
+
+                //fcTL and fdAT chunks share one sequence, the first frame is the default image
+                //so it keeps plain IDAT chunks
+                let mut sequence = 0u32;
+                for (index, data) in frames.iter().enumerate() {
+                    let mut control = Vec::new();
+                    control.extend_from_slice(&sequence.to_be_bytes());
+                    control.extend_from_slice(&self.width.to_be_bytes());
+                    control.extend_from_slice(&self.height.to_be_bytes());
+                    control.extend_from_slice(&0u32.to_be_bytes());
+                    control.extend_from_slice(&0u32.to_be_bytes());
+                    control.extend_from_slice(
+                        &(self.delay_ms.min(u16::MAX as u32) as u16).to_be_bytes(),
+                    );
+                    control.extend_from_slice(&1000u16.to_be_bytes());
+                    //Dispose and blend ops, every frame simply replaces the last
+                    control.extend_from_slice(&[0, 0]);
+                    write_chunk(&mut out, b"fcTL", &control).map_err(error)?;
+                    sequence += 1;
+
+                    if index == 0 {
+                        write_chunk(&mut out, b"IDAT", data).map_err(error)?;
+                    } else {
+                        let mut body = Vec::with_capacity(data.len() + 4);
+                        body.extend_from_slice(&sequence.to_be_bytes());
+                        body.extend_from_slice(data);
+                        write_chunk(&mut out, b"fdAT", &body).map_err(error)?;
+                        sequence += 1;
+                    }
+                }
+                write_chunk(&mut out, b"IEND", &[]).map_err(error)?;
+                out.flush().map_err(error)
+            }
+        }
+    }
+}
+
+//Write failures keep their io::Error, anything else is the frames not fitting the format
+fn gif_error(path: &str, e: EncodingError) -> Error {
+    match e {
+        EncodingError::Io(e) => Error::io(path, e),
+        e => Error::Options(format!("{}: {}", path, e)),
+    }
+}
+
+//Type and body of a PNG chunk
+type Chunk<'a> = (&'a [u8], &'a [u8]);
+
+//Every chunk in an encoded PNG, path is only for the error if it is cut short
+pub(crate) fn chunks<'a>(png: &'a [u8], path: &str) -> Result<Vec<Chunk<'a>>, Error> {
+    let mut chunks = Vec::new();
+    let mut position = 8;
+    while position < png.len() {
+        if position + 12 > png.len() {
+            return Err(Error::format(path, "PNG chunk cut short"));
+        }
+        let length = u32::from_be_bytes([
+            png[position],
+            png[position + 1],
+            png[position + 2],
+            png[position + 3],
+        ]) as usize;
+        if length > png.len() - position - 12 {
+            return Err(Error::format(path, "PNG chunk cut short"));
+        }
+        let kind = &png[position + 4..position + 8];
+        let body = &png[position + 8..position + 8 + length];
+        chunks.push((kind, body));
+        position += 12 + length;
+    }
+    Ok(chunks)
+}
+
+pub(crate) fn write_chunk<W: Write>(
+    out: &mut W,
+    kind: &[u8; 4],
+    body: &[u8],
+) -> std::io::Result<()> {
+    out.write_all(&(body.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(body)?;
+    let crc = crc32(crc32(0, kind), body);
+    out.write_all(&crc.to_be_bytes())
+}
+
+//CRC-32 as used by PNG, carrying on from a previous value so a chunk can be fed in pieces
+fn crc32(previous: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !previous;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
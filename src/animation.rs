@@ -0,0 +1,96 @@
+use crate::{BigFixed, Options};
+
+//Anything that gives the options for each frame of an animation
+pub trait Animation {
+    fn frames(&self) -> u32;
+
+    //Options for one frame, counting from 0. Settings the animation doesn't drive come from base
+    fn frame_options(&self, base: &Options, frame: u32) -> Options;
+}
+
+//Part of the plane on screen, the thing an animation moves between frames
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    pub centrex: BigFixed,
+    pub centrey: BigFixed,
+    pub scale: f64,
+}
+
+impl View {
+    pub fn from_options(options: &Options) -> View {
+        View {
+            centrex: options.centrex.clone(),
+            centrey: options.centrey.clone(),
+            scale: options.scaley,
+        }
+    }
+
+    pub fn apply(&self, options: &mut Options) {
+        options.centrex = self.centrex.clone();
+        options.centrey = self.centrey.clone();
+        options.scaley = self.scale;
+    }
+}
+
+//Zoom from one view to another. The scale changes by the same factor every frame so the zoom
+//looks like a constant speed, and the centre moves so the end centre stays at the same place on
+//screen the whole way, as if zooming straight in on it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zoom {
+    pub start: View,
+    pub end: View,
+    pub frames: u32,
+}
+
+impl Zoom {
+    //0 on the first frame and 1 on the last
+    fn progress(&self, frame: u32) -> f64 {
+        if self.frames > 1 {
+            frame as f64 / (self.frames - 1) as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub fn view(&self, frame: u32) -> View {
+        let t = self.progress(frame);
+        let (start, end) = (&self.start, &self.end);
+        let scale = if t < 1.0 {
+            start.scale * (end.scale / start.scale).powf(t)
+        } else {
+            end.scale
+        };
+
+        //Share of the way from the end centre back to the start centre. It shrinks in step with
+        //the scale, so a point at the end centre never moves across the screen
+        let weight = if start.scale != end.scale {
+            (scale - end.scale) / (start.scale - end.scale)
+        } else {
+            1.0 - t
+        };
+        //The weight gets as small as the zoom factor, so it needs that many more bits than an
+        //ordinary f64 to keep its precision at the end of a deep zoom
+        let depth = (start.scale / end.scale).abs().log2().max(0.0).ceil() as u32;
+        let weight = BigFixed::from_f64(weight, 64 + depth);
+        let centre = |from: &BigFixed, to: &BigFixed| {
+            to.clone() + (from.clone() - to.clone()) * weight.clone()
+        };
+        View {
+            centrex: centre(&start.centrex, &end.centrex),
+            centrey: centre(&start.centrey, &end.centrey),
+            scale,
+        }
+    }
+}
+
+impl Animation for Zoom {
+    fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    fn frame_options(&self, base: &Options, frame: u32) -> Options {
+        let mut options = base.clone();
+        self.view(frame).apply(&mut options);
+        options
+    }
+}
@@ -0,0 +1,156 @@
+use crate::data::{read_header, write_header, VERSION};
+use crate::{Error, Options, Pixel, RenderStats, Tile, TileScheduler};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+//Sidecar file holding the finished tiles of a render in progress so it can be resumed after the
+//process dies. Little endian:
+//
+//  8 bytes   magic "MANDCKPT"
+//  u32       format version
+//  u32       length of the settings text, then the text itself as key=value lines
+//  per finished tile, in the order they finished:
+//    u32 tile index, u64 cardioid count, u64 periodicity count
+//    per pixel in row order: the record Pixel::write_le writes, with the colour and with the
+//    extras only when the settings use them
+//
+//Tiles are only ever appended, so a tile cut short by the process dying is simply dropped on load
+const MAGIC: &[u8; 8] = b"MANDCKPT";
+
+//Path of the checkpoint kept next to an output image
+pub fn sidecar_path(image_path: &str) -> String {
+    format!("{}.checkpoint", image_path)
+}
+
+pub struct Checkpoint {
+    pub options: Options,
+    pub tiles: Vec<Tile>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Result<Checkpoint, Error> {
+        let error = |e: std::io::Error| Error::io(path, e);
+        let mut input = BufReader::new(File::open(path).map_err(error)?);
+        let options = read_header(&mut input, path, MAGIC, VERSION, "checkpoint")?;
+        let scheduler = TileScheduler::new(options.width, options.height, options.tile_size);
+        let uses_extras = options.uses_extras();
+        let record_size = Pixel::record_size(uses_extras, true);
+
+        let mut tiles = Vec::new();
+        let mut done = vec![false; scheduler.len() as usize];
+        let mut header = [0u8; 20];
+        loop {
+            match read_record(&mut input, &mut header) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Err(error(e)),
+            }
+            let index = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            if index >= scheduler.len() || done[index as usize] {
+                return Err(Error::format(path, format!("bad tile {}", index)));
+            }
+            let u64_at = |i: usize| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&header[i..i + 8]);
+                u64::from_le_bytes(bytes)
+            };
+
+            let rect = scheduler.rect(index);
+            let mut data = vec![0u8; (rect.width * rect.height) as usize * record_size];
+            match read_record(&mut input, &mut data) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Err(error(e)),
+            }
+            let mut pixels = Vec::with_capacity((rect.width * rect.height) as usize);
+            let mut extras = Vec::new();
+            for record in data.chunks_exact(record_size) {
+                let (pixel, pixel_extras) = Pixel::read_le(record, uses_extras, true);
+                pixels.push(pixel);
+                extras.extend(pixel_extras);
+            }
+            done[index as usize] = true;
+            tiles.push(Tile {
+                rect,
+                pixels,
+                extras,
+                thread_id: 0,
+                stats: RenderStats {
+                    cardioid: u64_at(4),
+                    periodicity: u64_at(12),
+                },
+            });
+        }
+        Ok(Checkpoint { options, tiles })
+    }
+}
+
+//Fills buf, false if the file ended first
+fn read_record<R: Read>(input: &mut R, buf: &mut [u8]) -> std::io::Result<bool> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+//Appends finished tiles to a checkpoint, writing them out to disk every interval
+pub struct CheckpointWriter {
+    path: String,
+    out: BufWriter<File>,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl CheckpointWriter {
+    //Starts a new checkpoint, replacing any file already at path
+    pub fn create(path: &str, options: &Options, interval: Duration) -> Result<Self, Error> {
+        let error = |e: std::io::Error| Error::io(path, e);
+        let mut out = BufWriter::new(File::create(path).map_err(error)?);
+        write_header(&mut out, MAGIC, VERSION, options).map_err(error)?;
+        out.flush().map_err(error)?;
+        Ok(CheckpointWriter {
+            path: String::from(path),
+            out,
+            interval,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn add(&mut self, tile: &Tile) -> Result<(), Error> {
+        self.write_tile(tile)
+            .and_then(|_| {
+                if self.last_flush.elapsed() >= self.interval {
+                    self.last_flush = Instant::now();
+                    self.out.flush()
+                } else {
+                    Ok(())
+                }
+            })
+            .map_err(|e| Error::io(&self.path, e))
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> std::io::Result<()> {
+        let out = &mut self.out;
+        out.write_all(&tile.rect.index.to_le_bytes())?;
+        out.write_all(&tile.stats.cardioid.to_le_bytes())?;
+        out.write_all(&tile.stats.periodicity.to_le_bytes())?;
+        for (i, pixel) in tile.pixels.iter().enumerate() {
+            pixel.write_le(out, tile.extras.get(i), true)?;
+        }
+        Ok(())
+    }
+
+    //Writes out every tile added so far
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.out.flush().map_err(|e| Error::io(&self.path, e))
+    }
+
+    //The render finished so the checkpoint is no longer needed
+    pub fn remove(self) -> Result<(), Error> {
+        let path = self.path;
+        drop(self.out);
+        std::fs::remove_file(&path).map_err(|e| Error::io(&path, e))
+    }
+}
@@ -0,0 +1,164 @@
+use crate::palette::pack;
+use crate::{Options, Pixel, PixelExtras, Trap};
+use std::fmt;
+use std::str::FromStr;
+
+//Which per-pixel value gets turned into a colour
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColourMode {
+    //Whole iteration counts, gives hard bands
+    Iterations,
+    //Normalised iteration count, continuous across band edges
+    Smooth,
+    //Estimated distance to the set, dark at the boundary so thin filaments show up
+    Distance,
+    //How close the orbit came to the trap, or the colour an image trap picked up
+    Trap,
+}
+
+impl FromStr for ColourMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColourMode, String> {
+        match s {
+            "iterations" => Ok(ColourMode::Iterations),
+            "smooth" => Ok(ColourMode::Smooth),
+            "distance" => Ok(ColourMode::Distance),
+            "trap" => Ok(ColourMode::Trap),
+            _ => Err(format!("Unknown colouring mode {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ColourMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ColourMode::Iterations => "iterations",
+            ColourMode::Smooth => "smooth",
+            ColourMode::Distance => "distance",
+            ColourMode::Trap => "trap",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//Normalised iteration count for a sample that escaped on iteration iter with |z|^2 = norm.
+//Lands in (iter, iter + 1] so neighbouring bands meet without a step
+#[inline]
+pub fn smooth_iterations(iter: u32, norm: f64, escape_radius: f64) -> f64 {
+    let log_ratio = (0.5 * norm.ln()) / escape_radius.ln();
+    iter as f64 + 1.0 - log_ratio.max(1.0).log2()
+}
+
+//Exterior distance estimate |z| ln|z| / |dz/dc| for a sample that escaped with |z|^2 = norm
+//and |dz/dc|^2 = derivative_norm. A bigger escape radius makes it more accurate
+#[inline]
+pub fn distance_estimate(norm: f64, derivative_norm: f64) -> f64 {
+    let distance = 0.5 * norm.sqrt() * norm.ln() / derivative_norm.sqrt();
+    if distance.is_finite() {
+        distance
+    } else {
+        0.0
+    }
+}
+
+//How quickly trap colouring fades with distance from the trap
+const TRAP_FALLOFF: f64 = 20.0;
+
+#[inline]
+pub fn iterations2colour(options: &Options, iter: f64, max_iter: u32, flags: u32) -> u32 {
+    let iter =
+        (iter * options.max_colours as f64 / max_iter as f64) as u32 & (options.max_colours - 1);
+    (((flags & 4) << 14) | ((flags & 2) << 7) | (flags & 1)) * iter
+}
+
+//Lighting strengths, the ambient and diffuse parts scale the colour and the highlight is added
+//on top as white
+const AMBIENT: f64 = 0.3;
+const DIFFUSE: f64 = 0.7;
+const SPECULAR: f64 = 0.4;
+const SHININESS: i32 = 20;
+
+//Lights a packed colour as if the pixel were a bit of surface tilted along its normal, with
+//Lambert diffuse and a Blinn-Phong highlight. Pixels without a normal are left alone
+pub fn shade(options: &Options, extras: &PixelExtras, colour: u32) -> u32 {
+    let [nx, ny] = extras.normal;
+    if nx == 0.0 && ny == 0.0 {
+        return colour;
+    }
+    let normalise = |v: [f64; 3]| {
+        let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        [v[0] / length, v[1] / length, v[2] / length]
+    };
+    let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+    //Image rows go down so the light's y is flipped. The viewer looks straight down
+    let normal = normalise([nx as f64, ny as f64, 1.0]);
+    let (sin, cos) = options.light_angle.to_radians().sin_cos();
+    let light = normalise([cos, -sin, options.light_height]);
+    let halfway = normalise([light[0], light[1], light[2] + 1.0]);
+
+    let lambert = dot(normal, light).max(0.0);
+    let highlight = dot(normal, halfway).max(0.0).powi(SHININESS) * SPECULAR * 255.0;
+    let channel =
+        |shift: u32| ((colour >> shift) & 0xff) as f64 * (AMBIENT + DIFFUSE * lambert) + highlight;
+    pack([channel(0), channel(8), channel(16)])
+}
+
+//Colour for a finished pixel using the colouring mode from options. Falls back to the
+//colour code when no palette is set. Pixels without extras get the default ones
+pub fn colour_pixel(options: &Options, pixel: &Pixel, extras: &PixelExtras, flags: u32) -> u32 {
+    let colour = base_colour(options, pixel, extras, flags);
+    if options.lighting {
+        shade(options, extras, colour)
+    } else {
+        colour
+    }
+}
+
+fn base_colour(options: &Options, pixel: &Pixel, extras: &PixelExtras, flags: u32) -> u32 {
+    if let (ColourMode::Trap, Some(Trap::Image(_))) = (options.colouring, &options.trap) {
+        return extras.texture;
+    }
+    let value = match options.colouring {
+        ColourMode::Iterations => pixel.iterations as f64,
+        ColourMode::Smooth => pixel.smooth as f64,
+        //Distance in pixels squashed into 0..1 so it fills one pass through the colours,
+        //half way is one pixel out
+        ColourMode::Distance => {
+            let distance = extras.distance as f64;
+            distance / (distance + 1.0) * options.max_iter as f64
+        }
+        //Brightest right on the trap, kept under 1 so the colour code bands don't wrap round
+        //to black
+        ColourMode::Trap => {
+            (-extras.trap as f64 * TRAP_FALLOFF).exp() * 0.999 * options.max_iter as f64
+        }
+    };
+    match &options.palette {
+        Some(palette) => palette.colour(value, options.max_iter, options.palette_offset),
+        //The colour code bands repeat every max_iter iterations, so the offset is a fraction of
+        //that. Interior pixels stay black
+        None if value > 0.0 => iterations2colour(
+            options,
+            value + options.palette_offset * options.max_iter as f64,
+            options.max_iter,
+            flags,
+        ),
+        None => iterations2colour(options, value, options.max_iter, flags),
+    }
+}
+
+//Colour every pixel again from its stored values, used when only the colouring options changed.
+//extras is either empty or one per pixel
+pub fn recolour(options: &Options, pixels: &mut [Pixel], extras: &[PixelExtras]) {
+    let none = PixelExtras::default();
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        pixel.colour = colour_pixel(
+            options,
+            pixel,
+            extras.get(i).unwrap_or(&none),
+            options.colour,
+        );
+    }
+}
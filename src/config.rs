@@ -0,0 +1,150 @@
+use crate::{Error, Options};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use toml::Value;
+
+//Famous views, each sets the centre, scale and iterations
+pub struct Preset {
+    pub name: &'static str,
+    pub centrex: &'static str,
+    pub centrey: &'static str,
+    pub scale: f64,
+    pub iterations: u32,
+}
+
+pub const PRESETS: [Preset; 5] = [
+    Preset {
+        name: "full",
+        centrex: "-0.75",
+        centrey: "0",
+        scale: 2.5,
+        iterations: 256,
+    },
+    Preset {
+        name: "seahorse-valley",
+        centrex: "-0.7453",
+        centrey: "0.1127",
+        scale: 0.0065,
+        iterations: 1000,
+    },
+    Preset {
+        name: "elephant-valley",
+        centrex: "0.2925",
+        centrey: "0.0149",
+        scale: 0.015,
+        iterations: 1000,
+    },
+    Preset {
+        name: "triple-spiral-valley",
+        centrex: "-0.0883",
+        centrey: "0.6549",
+        scale: 0.006,
+        iterations: 1000,
+    },
+    Preset {
+        name: "mini-brot-1",
+        centrex: "-1.7548776662466927",
+        centrey: "0",
+        scale: 0.05,
+        iterations: 1000,
+    },
+];
+
+impl Preset {
+    pub fn named(name: &str) -> Option<&'static Preset> {
+        PRESETS.iter().find(|preset| preset.name == name)
+    }
+
+    pub fn apply(&self, options: &mut Options) {
+        //The preset strings are always valid numbers
+        options.centrex = self.centrex.parse().unwrap();
+        options.centrey = self.centrey.parse().unwrap();
+        options.scaley = self.scale;
+        options.max_iter = self.iterations;
+    }
+}
+
+pub fn preset_names() -> Vec<&'static str> {
+    PRESETS.iter().map(|preset| preset.name).collect()
+}
+
+//Config file every run starts from if it exists, $XDG_CONFIG_HOME/mandelbrot/config.toml or
+//~/.config/mandelbrot/config.toml
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("mandelbrot").join("config.toml"))
+}
+
+//Config files are TOML with the same names as the command line options:
+//
+//  preset = "seahorse-valley"        (applied before the rest of the file)
+//  width = 1920
+//  height = 1080
+//  centrex = "-0.7436438870371587"   (a string keeps every digit)
+//  palette = "fire"
+//  colouring = "smooth"
+pub fn load(path: &str, options: &mut Options) -> Result<(), Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    apply(path, &text, options)
+}
+
+pub fn apply(name: &str, text: &str, options: &mut Options) -> Result<(), Error> {
+    let root: Value = text
+        .parse()
+        .map_err(|e: toml::de::Error| Error::format(name, e.to_string()))?;
+    let table = root
+        .as_table()
+        .ok_or_else(|| Error::format(name, "not a table"))?;
+
+    if let Some(preset) = table.get("preset") {
+        let preset = preset
+            .as_str()
+            .and_then(Preset::named)
+            .ok_or_else(|| Error::format(name, format!("unknown preset {}", preset)))?;
+        preset.apply(options);
+    }
+    for (key, value) in table.iter().filter(|(key, _)| *key != "preset") {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::Boolean(b) => b.to_string(),
+            _ => {
+                return Err(Error::format(
+                    name,
+                    format!("bad value {} for {}", value, key),
+                ))
+            }
+        };
+        options
+            .set_param(key, &value)
+            .map_err(|e| Error::format(name, e))?;
+    }
+    Ok(())
+}
+
+//The options as a config file that gives the same options back when loaded
+pub fn to_toml(options: &Options) -> String {
+    options
+        .to_params()
+        .iter()
+        .map(|(key, value)| {
+            //Numbers that f64 holds exactly and flags go in bare, anything else is quoted so
+            //no digits get lost
+            let bare = value.parse::<bool>().is_ok()
+                || value.parse::<i64>().is_ok()
+                || value
+                    .parse::<f64>()
+                    .is_ok_and(|f| f.is_finite() && f.to_string() == *value);
+            if bare {
+                format!("{} = {}\n", key, value)
+            } else {
+                format!("{} = {:?}\n", key, value)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,188 @@
+use crate::{colour, Error, Options, Pixel, PixelExtras};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+
+//Raw render data so an image can be recoloured without rendering it again. Little endian:
+//
+//  8 bytes   magic "MANDDATA"
+//  u32       format version
+//  u32       length of the settings text, then the text itself as key=value lines
+//  per pixel in row order: the record Pixel::write_le writes, without the colour and with the
+//  extras only when the settings use them
+const MAGIC: &[u8; 8] = b"MANDDATA";
+//Shared with the checkpoint format, so a change to the pixel record only bumps this
+pub(crate) const VERSION: u32 = 5;
+
+//Per pixel record shared with the checkpoint format. Little endian: u32 iterations, f32 smooth
+//iterations, f32 |z|, then for settings that use extras f32 distance, f32 x and y of the normal,
+//f32 trap distance and u32 trap image colour, then u32 colour for formats that keep it
+impl Pixel {
+    pub(crate) fn record_size(extras: bool, colour: bool) -> usize {
+        12 + if extras { 20 } else { 0 } + if colour { 4 } else { 0 }
+    }
+
+    pub(crate) fn write_le<W: Write>(
+        &self,
+        out: &mut W,
+        extras: Option<&PixelExtras>,
+        colour: bool,
+    ) -> std::io::Result<()> {
+        out.write_all(&self.iterations.to_le_bytes())?;
+        out.write_all(&self.smooth.to_le_bytes())?;
+        out.write_all(&self.magnitude.to_le_bytes())?;
+        if let Some(extras) = extras {
+            out.write_all(&extras.distance.to_le_bytes())?;
+            out.write_all(&extras.normal[0].to_le_bytes())?;
+            out.write_all(&extras.normal[1].to_le_bytes())?;
+            out.write_all(&extras.trap.to_le_bytes())?;
+            out.write_all(&extras.texture.to_le_bytes())?;
+        }
+        if colour {
+            out.write_all(&self.colour.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    //record holds record_size(extras, colour) bytes, the colour is left 0 when it isn't there
+    pub(crate) fn read_le(
+        record: &[u8],
+        extras: bool,
+        colour: bool,
+    ) -> (Pixel, Option<PixelExtras>) {
+        let field = |i: usize| [record[i], record[i + 1], record[i + 2], record[i + 3]];
+        let pixel = Pixel {
+            iterations: u32::from_le_bytes(field(0)),
+            smooth: f32::from_le_bytes(field(4)),
+            magnitude: f32::from_le_bytes(field(8)),
+            colour: if colour {
+                u32::from_le_bytes(field(Pixel::record_size(extras, false)))
+            } else {
+                0
+            },
+        };
+        let extras = if extras {
+            Some(PixelExtras {
+                distance: f32::from_le_bytes(field(12)),
+                normal: [f32::from_le_bytes(field(16)), f32::from_le_bytes(field(20))],
+                trap: f32::from_le_bytes(field(24)),
+                texture: u32::from_le_bytes(field(28)),
+            })
+        } else {
+            None
+        };
+        (pixel, extras)
+    }
+}
+
+//extras has to be one per pixel when the options use them
+pub fn save(
+    path: &str,
+    options: &Options,
+    pixels: &[Pixel],
+    extras: &[PixelExtras],
+) -> Result<(), Error> {
+    let error = |e: std::io::Error| Error::io(path, e);
+    let mut out = BufWriter::new(File::create(path).map_err(error)?);
+
+    write_header(&mut out, MAGIC, VERSION, options).map_err(error)?;
+    let uses_extras = options.uses_extras();
+    for (i, pixel) in pixels.iter().enumerate() {
+        let extras = if uses_extras { Some(&extras[i]) } else { None };
+        pixel.write_le(&mut out, extras, false).map_err(error)?;
+    }
+    out.flush().map_err(error)
+}
+
+//Options the data was rendered with and its pixels and extras, coloured with those options.
+//extras is empty when the options don't use them
+pub fn load(path: &str) -> Result<(Options, Vec<Pixel>, Vec<PixelExtras>), Error> {
+    let error = |e: std::io::Error| Error::io(path, e);
+    let file = File::open(path).map_err(error)?;
+    let length = file.metadata().map_err(error)?.len();
+    let mut input = BufReader::new(file);
+    let options = read_header(&mut input, path, MAGIC, VERSION, "render data")?;
+
+    let count = options.width as usize * options.height as usize;
+    let uses_extras = options.uses_extras();
+    //A damaged size mustn't get to ask for more memory than the file could fill
+    let left = length.saturating_sub(input.stream_position().map_err(error)?);
+    let size = (count as u64).checked_mul(Pixel::record_size(uses_extras, false) as u64);
+    if size.is_none_or(|size| size > left) {
+        return Err(Error::format(path, "pixel data is cut short"));
+    }
+    let mut pixels = Vec::with_capacity(count);
+    let mut extras = Vec::new();
+    let mut record = vec![0u8; Pixel::record_size(uses_extras, false)];
+    for _ in 0..count {
+        input.read_exact(&mut record).map_err(error)?;
+        let (pixel, pixel_extras) = Pixel::read_le(&record, uses_extras, false);
+        pixels.push(pixel);
+        extras.extend(pixel_extras);
+    }
+    colour::recolour(&options, &mut pixels, &extras);
+    Ok((options, pixels, extras))
+}
+
+//Magic, version and the settings text, shared with the checkpoint format
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    magic: &[u8; 8],
+    version: u32,
+    options: &Options,
+) -> std::io::Result<()> {
+    let params: String = options
+        .to_params()
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect();
+    out.write_all(magic)?;
+    out.write_all(&version.to_le_bytes())?;
+    out.write_all(&(params.len() as u32).to_le_bytes())?;
+    out.write_all(params.as_bytes())
+}
+
+pub(crate) fn read_header<R: Read>(
+    input: &mut R,
+    path: &str,
+    magic: &[u8; 8],
+    version: u32,
+    kind: &str,
+) -> Result<Options, Error> {
+    let error = |e: std::io::Error| Error::io(path, e);
+    let read_u32 = |input: &mut R| -> Result<u32, Error> {
+        let mut bytes = [0u8; 4];
+        input.read_exact(&mut bytes).map_err(error)?;
+        Ok(u32::from_le_bytes(bytes))
+    };
+
+    let mut found = [0u8; 8];
+    input.read_exact(&mut found).map_err(error)?;
+    if &found != magic {
+        return Err(Error::format(path, format!("not a {} file", kind)));
+    }
+    let found = read_u32(input)?;
+    if found != version {
+        return Err(Error::format(
+            path,
+            format!("unsupported {} version {}", kind, found),
+        ));
+    }
+
+    let mut params = vec![0u8; read_u32(input)? as usize];
+    input.read_exact(&mut params).map_err(error)?;
+    let params = String::from_utf8(params).map_err(|_| Error::format(path, "bad settings"))?;
+
+    let pairs = params
+        .lines()
+        .map(|line| {
+            line.split_once('=')
+                .ok_or_else(|| Error::format(path, format!("could not parse setting {:?}", line)))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let options = Options::from_params(pairs).map_err(|e| Error::format(path, e))?;
+    //The file may be damaged, so its settings get no more trust than the command line's
+    options
+        .validate()
+        .map_err(|e| Error::format(path, e.to_string()))?;
+    Ok(options)
+}
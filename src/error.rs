@@ -0,0 +1,63 @@
+use std::fmt;
+use std::io;
+
+//Everything that can go wrong rendering or reading and writing files
+#[derive(Debug)]
+pub enum Error {
+    //Settings that can't make an image
+    Options(String),
+    //Reading or writing a file failed, with the path
+    Io(String, io::Error),
+    //Encoding or decoding an image failed, with the path
+    Image(String, image::ImageError),
+    //A file was read but doesn't hold what it should, with the path and what is wrong
+    Format(String, String),
+    //The workers stopped before every tile was done
+    Render(String),
+}
+
+impl Error {
+    pub fn io(path: &str, error: io::Error) -> Error {
+        Error::Io(String::from(path), error)
+    }
+
+    pub fn image(path: &str, error: image::ImageError) -> Error {
+        Error::Image(String::from(path), error)
+    }
+
+    pub fn format<M: Into<String>>(path: &str, message: M) -> Error {
+        Error::Format(String::from(path), message.into())
+    }
+
+    //Process exit code for the error. 1 is left for anything outside the library and 2 is
+    //what argument parsing already uses for bad arguments
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Options(_) => 2,
+            Error::Io(_, _) | Error::Image(_, _) => 3,
+            Error::Format(_, _) => 4,
+            Error::Render(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Options(message) | Error::Render(message) => write!(f, "{}", message),
+            Error::Io(path, error) => write!(f, "{}: {}", path, error),
+            Error::Image(path, error) => write!(f, "{}: {}", path, error),
+            Error::Format(path, message) => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, error) => Some(error),
+            Error::Image(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::precision::Real;
+use crate::Formula;
+use std::fmt;
+use std::str::FromStr;
+
+//Formula selected on the command line. Parameters live in Options next to it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FormulaType {
+    Mandelbrot,
+    Multibrot,
+    BurningShip,
+    Tricorn,
+    Celtic,
+    Phoenix,
+}
+
+pub const FORMULAS: [&str; 6] = [
+    "mandelbrot",
+    "multibrot",
+    "burning-ship",
+    "tricorn",
+    "celtic",
+    "phoenix",
+];
+
+impl FromStr for FormulaType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FormulaType, String> {
+        match s {
+            "mandelbrot" => Ok(FormulaType::Mandelbrot),
+            "multibrot" => Ok(FormulaType::Multibrot),
+            "burning-ship" => Ok(FormulaType::BurningShip),
+            "tricorn" | "mandelbar" => Ok(FormulaType::Tricorn),
+            "celtic" => Ok(FormulaType::Celtic),
+            "phoenix" => Ok(FormulaType::Phoenix),
+            _ => Err(format!("Unknown formula {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for FormulaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FormulaType::Mandelbrot => FORMULAS[0],
+            FormulaType::Multibrot => FORMULAS[1],
+            FormulaType::BurningShip => FORMULAS[2],
+            FormulaType::Tricorn => FORMULAS[3],
+            FormulaType::Celtic => FORMULAS[4],
+            FormulaType::Phoenix => FORMULAS[5],
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//Enough fractional bits to hold an f64 of ordinary magnitude when the working type is BigFixed
+const F64_BITS: u32 = 64;
+
+#[inline]
+fn twice<R: Real>(value: R) -> R {
+    value.clone() + value
+}
+
+#[inline]
+fn complex_mul<R: Real>(a: &(R, R), b: &(R, R)) -> (R, R) {
+    (
+        a.0.clone() * b.0.clone() - a.1.clone() * b.1.clone(),
+        a.0.clone() * b.1.clone() + a.1.clone() * b.0.clone(),
+    )
+}
+
+//z^2 + c
+pub struct Mandelbrot;
+
+impl Formula for Mandelbrot {
+    #[inline]
+    fn step<R: Real>(&self, z: (R, R), squares: (R, R), _: &(R, R), c: &(R, R)) -> (R, R) {
+        let (x2, y2) = squares;
+        (x2 - y2 + c.0.clone(), twice(z.0 * z.1) + c.1.clone())
+    }
+
+    //Main cardioid and period 2 bulb, worked out in the working precision so it stays right
+    //at deep zooms near their edges
+    fn known_interior<R: Real>(&self, c: &(R, R)) -> bool {
+        let quarter = R::from_f64(0.25, F64_BITS);
+        let x = c.0.clone() - quarter.clone();
+        let y2 = c.1.clone() * c.1.clone();
+        let q = x.clone() * x.clone() + y2.clone();
+        if (q.clone() * (q + x) - quarter * y2.clone()).to_f64() <= 0.0 {
+            return true;
+        }
+        let x = c.0.clone() + R::from_f64(1.0, F64_BITS);
+        (x.clone() * x + y2 - R::from_f64(0.0625, F64_BITS)).to_f64() <= 0.0
+    }
+}
+
+//z^power + c. Whole powers stay in the working precision, anything else goes through polar
+//form in f64 so it loses precision at deep zooms
+pub struct Multibrot {
+    pub power: f64,
+}
+
+impl Formula for Multibrot {
+    fn step<R: Real>(&self, z: (R, R), _: (R, R), _: &(R, R), c: &(R, R)) -> (R, R) {
+        let (x, y) = if self.power.fract() == 0.0 && self.power >= 1.0 {
+            let mut power = self.power as u64;
+            let mut base = z;
+            let mut result: Option<(R, R)> = None;
+            while power > 0 {
+                if power & 1 == 1 {
+                    result = Some(match result {
+                        Some(result) => complex_mul(&result, &base),
+                        None => base.clone(),
+                    });
+                }
+                power >>= 1;
+                if power > 0 {
+                    base = complex_mul(&base, &base);
+                }
+            }
+            result.unwrap()
+        } else {
+            let (x, y) = (z.0.to_f64(), z.1.to_f64());
+            let radius = (x * x + y * y).sqrt().powf(self.power);
+            let angle = y.atan2(x) * self.power;
+            let bits = F64_BITS;
+            (
+                R::from_f64(radius * angle.cos(), bits),
+                R::from_f64(radius * angle.sin(), bits),
+            )
+        };
+        (x + c.0.clone(), y + c.1.clone())
+    }
+}
+
+//(|x| + i|y|)^2 + c
+pub struct BurningShip;
+
+impl Formula for BurningShip {
+    #[inline]
+    fn step<R: Real>(&self, z: (R, R), squares: (R, R), _: &(R, R), c: &(R, R)) -> (R, R) {
+        let (x2, y2) = squares;
+        (
+            x2 - y2 + c.0.clone(),
+            twice((z.0 * z.1).abs()) + c.1.clone(),
+        )
+    }
+}
+
+//conj(z)^2 + c, also known as the Mandelbar set
+pub struct Tricorn;
+
+impl Formula for Tricorn {
+    #[inline]
+    fn step<R: Real>(&self, z: (R, R), squares: (R, R), _: &(R, R), c: &(R, R)) -> (R, R) {
+        let (x2, y2) = squares;
+        (x2 - y2 + c.0.clone(), c.1.clone() - twice(z.0 * z.1))
+    }
+}
+
+//Like the Mandelbrot set but with the absolute value of the real part of z^2
+pub struct Celtic;
+
+impl Formula for Celtic {
+    #[inline]
+    fn step<R: Real>(&self, z: (R, R), squares: (R, R), _: &(R, R), c: &(R, R)) -> (R, R) {
+        let (x2, y2) = squares;
+        (
+            (x2 - y2).abs() + c.0.clone(),
+            twice(z.0 * z.1) + c.1.clone(),
+        )
+    }
+}
+
+//z^2 + c + p z_n-1 with a real constant p
+pub struct Phoenix {
+    pub p: f64,
+}
+
+impl Formula for Phoenix {
+    const USES_PREVIOUS: bool = true;
+
+    #[inline]
+    fn step<R: Real>(&self, z: (R, R), squares: (R, R), previous: &(R, R), c: &(R, R)) -> (R, R) {
+        let (x2, y2) = squares;
+        let p = R::from_f64(self.p, F64_BITS);
+        (
+            x2 - y2 + c.0.clone() + p.clone() * previous.0.clone(),
+            twice(z.0 * z.1) + c.1.clone() + p * previous.1.clone(),
+        )
+    }
+}
@@ -0,0 +1,295 @@
+use crate::animation::Animation;
+use crate::{BigFixed, Error, Options};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use toml::Value;
+
+//How values are blended between keyframes. Scale is always blended as its logarithm so zooms
+//run at a steady speed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    //Smooth curve through every keyframe, no sudden change of speed at a keyframe
+    CatmullRom,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Interpolation, String> {
+        match s {
+            "linear" => Ok(Interpolation::Linear),
+            "catmull-rom" | "cubic" => Ok(Interpolation::CatmullRom),
+            _ => Err(format!("Unknown interpolation {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Interpolation::Linear => "linear",
+            Interpolation::CatmullRom => "catmull-rom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Interpolation {
+    //Weights of the keyframes before, at the start of, at the end of and after a segment for a
+    //point u of the way along it. They always add up to 1
+    fn weights(self, u: f64) -> [f64; 4] {
+        match self {
+            Interpolation::Linear => [0.0, 1.0 - u, u, 0.0],
+            Interpolation::CatmullRom => {
+                let (u2, u3) = (u * u, u * u * u);
+                [
+                    0.5 * (-u3 + 2.0 * u2 - u),
+                    0.5 * (3.0 * u3 - 5.0 * u2 + 2.0),
+                    0.5 * (-3.0 * u3 + 4.0 * u2 + u),
+                    0.5 * (u3 - u2),
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    //Seconds from the start of the animation
+    pub time: f64,
+    pub centrex: BigFixed,
+    pub centrey: BigFixed,
+    pub scale: f64,
+    pub rotation: f64,
+    pub max_iter: u32,
+    pub palette_offset: f64,
+}
+
+//Camera path for an animation. Keyframe files are TOML:
+//
+//  fps = 30
+//  interpolation = "catmull-rom"     (or "linear")
+//
+//  [[keyframe]]
+//  time = 0.0
+//  centrex = "-0.75"                 (a string keeps every digit, a number is read as an f64)
+//  centrey = 0.0
+//  scale = 2.5
+//  rotation = 0.0                    (degrees)
+//  iterations = 256
+//  palette-offset = 0.0
+//
+//Anything a keyframe leaves out is the same as in the keyframe before it, the first keyframe
+//takes missing values from the command line options
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframes {
+    pub keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+    pub fps: f64,
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float(f) => Some(*f),
+        Value::Integer(i) => Some(*i as f64),
+        _ => None,
+    }
+}
+
+fn fixed(value: &Value) -> Option<BigFixed> {
+    match value {
+        Value::String(s) => s.parse().ok(),
+        value => number(value).map(BigFixed::from),
+    }
+}
+
+impl Keyframes {
+    pub fn load(path: &str, base: &Options) -> Result<Keyframes, Error> {
+        let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Keyframes::parse(path, &text, base)
+    }
+
+    pub fn parse(name: &str, text: &str, base: &Options) -> Result<Keyframes, Error> {
+        let root: Value = text
+            .parse()
+            .map_err(|e: toml::de::Error| Error::format(name, e.to_string()))?;
+        let fps = match root.get("fps") {
+            Some(value) => number(value)
+                .filter(|fps| *fps > 0.0)
+                .ok_or_else(|| Error::format(name, "fps must be a positive number"))?,
+            None => 30.0,
+        };
+        let interpolation = match root.get("interpolation") {
+            Some(value) => value
+                .as_str()
+                .ok_or_else(|| Error::format(name, "interpolation must be a string"))?
+                .parse()
+                .map_err(|e| Error::format(name, e))?,
+            None => Interpolation::CatmullRom,
+        };
+
+        let mut previous = Keyframe {
+            time: 0.0,
+            centrex: base.centrex.clone(),
+            centrey: base.centrey.clone(),
+            scale: base.scaley,
+            rotation: base.rotation,
+            max_iter: base.max_iter,
+            palette_offset: base.palette_offset,
+        };
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        let tables = root
+            .get("keyframe")
+            .and_then(Value::as_array)
+            .filter(|tables| !tables.is_empty())
+            .ok_or_else(|| Error::format(name, "no [[keyframe]] entries"))?;
+        for (number_in_file, table) in tables.iter().enumerate() {
+            let table = table.as_table().ok_or_else(|| {
+                Error::format(
+                    name,
+                    format!("keyframe {} is not a table", number_in_file + 1),
+                )
+            })?;
+            let mut keyframe = previous.clone();
+            for (key, value) in table {
+                let bad = || {
+                    Error::format(
+                        name,
+                        format!(
+                            "keyframe {}: bad value {} for {}",
+                            number_in_file + 1,
+                            value,
+                            key
+                        ),
+                    )
+                };
+                match key.as_str() {
+                    "time" => keyframe.time = number(value).ok_or_else(bad)?,
+                    "centrex" => keyframe.centrex = fixed(value).ok_or_else(bad)?,
+                    "centrey" => keyframe.centrey = fixed(value).ok_or_else(bad)?,
+                    "scale" => {
+                        keyframe.scale = number(value).filter(|s| *s > 0.0).ok_or_else(bad)?
+                    }
+                    "rotation" => keyframe.rotation = number(value).ok_or_else(bad)?,
+                    "iterations" => {
+                        keyframe.max_iter = value
+                            .as_integer()
+                            .filter(|i| *i > 0 && *i <= u32::MAX as i64)
+                            .ok_or_else(bad)? as u32
+                    }
+                    "palette-offset" => keyframe.palette_offset = number(value).ok_or_else(bad)?,
+                    _ => {
+                        return Err(Error::format(
+                            name,
+                            format!("keyframe {}: unknown setting {}", number_in_file + 1, key),
+                        ))
+                    }
+                }
+            }
+            if !table.contains_key("time") {
+                return Err(Error::format(
+                    name,
+                    format!("keyframe {}: missing time", number_in_file + 1),
+                ));
+            }
+            if keyframes
+                .last()
+                .is_some_and(|last| keyframe.time <= last.time)
+            {
+                return Err(Error::format(
+                    name,
+                    format!("keyframe {}: times must increase", number_in_file + 1),
+                ));
+            }
+            previous = keyframe.clone();
+            keyframes.push(keyframe);
+        }
+
+        Ok(Keyframes {
+            keyframes,
+            interpolation,
+            fps,
+        })
+    }
+
+    fn duration(&self) -> f64 {
+        self.keyframes[self.keyframes.len() - 1].time - self.keyframes[0].time
+    }
+
+    //Blend of the keyframes at a time in seconds from the first keyframe
+    pub fn at(&self, time: f64) -> Keyframe {
+        let keys = &self.keyframes;
+        let time = keys[0].time + time.clamp(0.0, self.duration());
+        if keys.len() == 1 {
+            return keys[0].clone();
+        }
+
+        //Segment the time falls in, neighbours past either end repeat the end keyframe
+        let segment = keys[1..]
+            .iter()
+            .position(|k| time <= k.time)
+            .unwrap_or(keys.len() - 2);
+        let last = keys.len() - 1;
+        let around = [
+            &keys[segment.saturating_sub(1)],
+            &keys[segment],
+            &keys[segment + 1],
+            &keys[(segment + 2).min(last)],
+        ];
+        let u = (time - around[1].time) / (around[2].time - around[1].time);
+        let weights = self.interpolation.weights(u);
+
+        let blend = |value: &dyn Fn(&Keyframe) -> f64| -> f64 {
+            around
+                .iter()
+                .zip(weights.iter())
+                .map(|(k, w)| value(k) * w)
+                .sum()
+        };
+        //Centres are blended as offsets from the start of the segment so none of their
+        //precision is lost
+        let bits = around
+            .iter()
+            .map(|k| k.centrex.bits().max(k.centrey.bits()))
+            .max()
+            .unwrap();
+        let centre = |value: &dyn Fn(&Keyframe) -> &BigFixed| -> BigFixed {
+            let from = value(around[1]);
+            let mut result = from.clone();
+            for (k, w) in around.iter().zip(weights.iter()) {
+                result = result + (value(k).clone() - from.clone()) * BigFixed::from_f64(*w, bits);
+            }
+            result
+        };
+
+        Keyframe {
+            time,
+            centrex: centre(&|k| &k.centrex),
+            centrey: centre(&|k| &k.centrey),
+            scale: blend(&|k| k.scale.ln()).exp(),
+            rotation: blend(&|k| k.rotation),
+            max_iter: blend(&|k| k.max_iter as f64).round().max(1.0) as u32,
+            palette_offset: blend(&|k| k.palette_offset),
+        }
+    }
+}
+
+impl Animation for Keyframes {
+    fn frames(&self) -> u32 {
+        (self.duration() * self.fps).floor() as u32 + 1
+    }
+
+    fn frame_options(&self, base: &Options, frame: u32) -> Options {
+        let keyframe = self.at(frame as f64 / self.fps);
+        let mut options = base.clone();
+        options.centrex = keyframe.centrex;
+        options.centrey = keyframe.centrey;
+        options.scaley = keyframe.scale;
+        options.rotation = keyframe.rotation;
+        options.max_iter = keyframe.max_iter;
+        options.palette_offset = keyframe.palette_offset;
+        options
+    }
+}
@@ -1,21 +1,91 @@
 use std::fmt;
+use std::str::FromStr;
 use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+
+pub mod animated;
+pub mod animation;
+pub mod checkpoint;
+pub mod colour;
+pub mod config;
+pub mod data;
+pub mod error;
+pub mod formula;
+pub mod keyframes;
+pub mod metadata;
+pub mod palette;
+pub mod perturbation;
+pub mod precision;
+pub mod pyramid;
+pub mod renderer;
+pub mod server;
+pub mod simd;
+pub mod stream;
+pub mod tiles;
+pub mod trap;
+
+pub use animated::AnimatedWriter;
+pub use animation::{Animation, View, Zoom};
+pub use colour::ColourMode;
+pub use error::Error;
+pub use formula::FormulaType;
+pub use keyframes::{Interpolation, Keyframes};
+pub use palette::Palette;
+pub use perturbation::{perturbation, ReferenceOrbit};
+pub use precision::{BigFixed, DoubleDouble, Precision, Real};
+pub use pyramid::Pyramid;
+pub use renderer::{RenderResult, Renderer};
+pub use server::TileServer;
+pub use stream::PngStream;
+pub use tiles::{RenderStats, Tile, TileRect, TileScheduler};
+pub use trap::Trap;
+use trap::TrapTracker;
 
 //Struct for storing arguments
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Options {
     pub max_colours: u32,
     pub max_iter: u32,
 
     pub width: u32,
     pub height: u32,
-    pub centrex: f32,
-    pub centrey: f32,
-    pub scaley: f32,
+    pub centrex: BigFixed,
+    pub centrey: BigFixed,
+    pub scaley: f64,
+    //Turn of the view about its centre in degrees
+    pub rotation: f64,
+    pub precision: Precision,
+    //Use the vectorised kernel where the cpu and precision allow it
+    pub simd: bool,
+    //Constant c for Julia mode, where every pixel is the starting z instead
+    pub julia: Option<(BigFixed, BigFixed)>,
+    pub formula: FormulaType,
+    //Exponent for the multibrot formula
+    pub power: f64,
+    //Weight of the previous z for the phoenix formula
+    pub phoenix_p: f64,
+    pub perturbation: bool,
+    pub series_approximation: bool,
+    //Skip iterating points in the main cardioid or period 2 bulb
+    pub cardioid_check: bool,
+    //Stop iterating once the orbit repeats itself
+    pub periodicity_check: bool,
 
     pub samples: u32,
+    //Width and height of the squares the image is split into for the workers
+    pub tile_size: u32,
     pub colour: u32,
+    pub colouring: ColourMode,
+    pub palette: Option<Palette>,
+    pub palette_offset: f64,
+    //Shape the orbit is measured against for trap colouring
+    pub trap: Option<Trap>,
+    pub escape_radius: f64,
+    //Shade the set as an embossed surface lit from light_angle degrees anticlockwise from the
+    //right of the image, light_height above it
+    pub lighting: bool,
+    pub light_angle: f64,
+    pub light_height: f64,
     pub colourise: bool,
     pub threads: u32,
     pub thread_id: Option<u32>,
@@ -28,9 +98,9 @@ impl Options {
         max_iter: u32,
         width: u32,
         height: u32,
-        centrex: f32,
-        centrey: f32,
-        scaley: f32,
+        centrex: BigFixed,
+        centrey: BigFixed,
+        scaley: f64,
         samples: u32,
         colour: u32,
         colourise: bool,
@@ -45,21 +115,275 @@ impl Options {
             centrex,
             centrey,
             scaley,
+            rotation: 0.0,
+            precision: Precision::Auto,
+            simd: true,
+            julia: None,
+            formula: FormulaType::Mandelbrot,
+            power: 3.0,
+            phoenix_p: -0.5,
+            perturbation: false,
+            series_approximation: false,
+            cardioid_check: true,
+            periodicity_check: true,
             samples,
+            tile_size: 64,
             colour,
+            colouring: ColourMode::Iterations,
+            palette: None,
+            palette_offset: 0.0,
+            trap: None,
+            escape_radius: 2.0,
+            lighting: false,
+            light_angle: 45.0,
+            light_height: 1.0,
             colourise,
             threads,
             thread_id: None,
             progress,
         }
     }
+
+    //Checks the options can make an image before anything is rendered with them
+    pub fn validate(&self) -> Result<(), Error> {
+        let problem = if self.width == 0 || self.height == 0 {
+            Some(format!(
+                "Image size {}x{} is empty, width and height must be at least 1",
+                self.width, self.height
+            ))
+        } else if self.samples == 0 {
+            Some(String::from("Samples must be at least 1"))
+        } else if self.threads == 0 {
+            Some(String::from("Threads must be at least 1"))
+        } else if self.tile_size == 0 {
+            Some(String::from("Tile size must be at least 1"))
+        } else if !(self.scaley.is_finite() && self.scaley > 0.0) {
+            Some(format!("Scale {} must be a number above 0", self.scaley))
+        } else if self.max_iter == 0 {
+            Some(String::from("Iterations must be at least 1"))
+        } else if !self.palette_offset.is_finite() {
+            Some(format!(
+                "Palette offset {} is not a number",
+                self.palette_offset
+            ))
+        } else if self.uses_derivative() && self.formula != FormulaType::Mandelbrot {
+            Some(format!(
+                "Distance colouring and lighting need the mandelbrot formula, not {}",
+                self.formula
+            ))
+        } else if self.escape_radius * self.escape_radius >= BigFixed::LIMIT
+            && self.precision.resolve(self.sample_size()).0 == Precision::Arbitrary
+        {
+            Some(format!(
+                "Escape radius {} is too big for arbitrary precision, it must be below {}",
+                self.escape_radius,
+                BigFixed::LIMIT.sqrt()
+            ))
+        } else if self.colouring == ColourMode::Trap && self.trap.is_none() {
+            Some(String::from("Trap colouring needs a trap shape"))
+        } else if !self.max_colours.is_power_of_two() {
+            //Colour codes pick a band with iter & (max_colours - 1)
+            Some(format!(
+                "Max colours {} is not a power of two",
+                self.max_colours
+            ))
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) => Err(Error::Options(problem)),
+            None => Ok(()),
+        }
+    }
+
+    //Whether the workers have to carry the derivative along for the distance estimate or normal
+    pub fn uses_derivative(&self) -> bool {
+        self.colouring == ColourMode::Distance || self.lighting
+    }
+
+    //Whether pixels need PixelExtras worked out and kept
+    pub fn uses_extras(&self) -> bool {
+        self.uses_derivative() || self.active_trap().is_some()
+    }
+
+    //The trap when the workers have to follow orbits for it
+    pub fn active_trap(&self) -> Option<&Trap> {
+        self.trap
+            .as_ref()
+            .filter(|_| self.colouring == ColourMode::Trap)
+    }
+
+    //Distance between neighbouring samples in the complex plane
+    pub fn sample_size(&self) -> f64 {
+        self.scaley / self.height as f64 / self.samples as f64
+    }
+
+    //Every setting that affects the render as key value pairs, keys match the command line
+    //flags. set_param reads them back
+    pub fn to_params(&self) -> Vec<(String, String)> {
+        let mut params = vec![
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            ("centrex", self.centrex.to_string()),
+            ("centrey", self.centrey.to_string()),
+            ("scale", self.scaley.to_string()),
+            ("rotation", self.rotation.to_string()),
+            ("iterations", self.max_iter.to_string()),
+            ("max-colours", self.max_colours.to_string()),
+            ("precision", self.precision.to_string()),
+            ("simd", self.simd.to_string()),
+            ("formula", self.formula.to_string()),
+            ("power", self.power.to_string()),
+            ("phoenix-p", self.phoenix_p.to_string()),
+            ("perturbation", self.perturbation.to_string()),
+            (
+                "series-approximation",
+                self.series_approximation.to_string(),
+            ),
+            ("cardioid-check", self.cardioid_check.to_string()),
+            ("periodicity-check", self.periodicity_check.to_string()),
+            ("samples", self.samples.to_string()),
+            ("tile-size", self.tile_size.to_string()),
+            ("colour", self.colour.to_string()),
+            ("colouring", self.colouring.to_string()),
+            ("palette-offset", self.palette_offset.to_string()),
+            ("escape-radius", self.escape_radius.to_string()),
+            ("lighting", self.lighting.to_string()),
+            ("light-angle", self.light_angle.to_string()),
+            ("light-height", self.light_height.to_string()),
+            ("colourise", self.colourise.to_string()),
+            ("threads", self.threads.to_string()),
+        ];
+        if let Some((re, im)) = &self.julia {
+            params.push(("julia-re", re.to_string()));
+            params.push(("julia-im", im.to_string()));
+        }
+        //Palette files go in whole so the settings don't need the file to be there
+        match &self.palette {
+            Some(palette) if palette.is_builtin() => params.push(("palette", palette.to_string())),
+            Some(palette) => params.push(("palette-stops", palette.to_inline())),
+            None => {}
+        }
+        if let Some(trap) = &self.trap {
+            params.push(("trap", trap.to_string()));
+        }
+        params
+            .into_iter()
+            .map(|(key, value)| (String::from(key), value))
+            .collect()
+    }
+
+    //Options made from saved settings alone. Every setting to_params always writes has to be
+    //there, the blank values standing in for them can't make an image
+    pub fn from_params<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(
+        params: I,
+    ) -> Result<Options, String> {
+        let mut options = Options::new(
+            0,
+            0,
+            0,
+            0,
+            BigFixed::from(0.0),
+            BigFixed::from(0.0),
+            0.0,
+            1,
+            0,
+            false,
+            1,
+            false,
+        );
+        let mut missing: Vec<String> = options
+            .to_params()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        for (key, value) in params {
+            options.set_param(key, value)?;
+            missing.retain(|required| required != key);
+        }
+        if !missing.is_empty() {
+            return Err(format!("Missing settings {}", missing.join(", ")));
+        }
+        Ok(options)
+    }
+
+    pub fn set_param(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value {} for {}", value, key))
+        }
+        match key {
+            "width" => self.width = parse(key, value)?,
+            "height" => self.height = parse(key, value)?,
+            "centrex" => self.centrex = parse(key, value)?,
+            "centrey" => self.centrey = parse(key, value)?,
+            "scale" => self.scaley = parse(key, value)?,
+            "rotation" => self.rotation = parse(key, value)?,
+            "iterations" => self.max_iter = parse(key, value)?,
+            "max-colours" => self.max_colours = parse(key, value)?,
+            "precision" => self.precision = parse(key, value)?,
+            "simd" => self.simd = parse(key, value)?,
+            "formula" => self.formula = parse(key, value)?,
+            "power" => self.power = parse(key, value)?,
+            "phoenix-p" => self.phoenix_p = parse(key, value)?,
+            "perturbation" => self.perturbation = parse(key, value)?,
+            "series-approximation" => self.series_approximation = parse(key, value)?,
+            "cardioid-check" => self.cardioid_check = parse(key, value)?,
+            "periodicity-check" => self.periodicity_check = parse(key, value)?,
+            "samples" => self.samples = parse(key, value)?,
+            "tile-size" => self.tile_size = parse(key, value)?,
+            "colour" => self.colour = parse(key, value)?,
+            "colouring" => self.colouring = parse(key, value)?,
+            "palette" => self.palette = Some(value.parse()?),
+            "palette-stops" => self.palette = Some(Palette::parse_inline(value)?),
+            "palette-offset" => self.palette_offset = parse(key, value)?,
+            "trap" => self.trap = Some(value.parse()?),
+            "escape-radius" => self.escape_radius = parse(key, value)?,
+            "lighting" => self.lighting = parse(key, value)?,
+            "light-angle" => self.light_angle = parse(key, value)?,
+            "light-height" => self.light_height = parse(key, value)?,
+            "colourise" => self.colourise = parse(key, value)?,
+            "threads" => self.threads = parse(key, value)?,
+            //Either part of the Julia constant switches to Julia mode, the other stays zero
+            "julia-re" | "julia-im" => {
+                let part: BigFixed = parse(key, value)?;
+                let (re, im) = self
+                    .julia
+                    .take()
+                    .unwrap_or_else(|| (BigFixed::from(0.0), BigFixed::from(0.0)));
+                self.julia = Some(if key == "julia-re" {
+                    (part, im)
+                } else {
+                    (re, part)
+                });
+            }
+            _ => return Err(format!("Unknown setting {}", key)),
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Options {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let colour = match &self.palette {
+            Some(palette) => format!("palette {}", palette),
+            None => format!("colour code {}", self.colour),
+        };
+        if let Some((re, im)) = &self.julia {
+            write!(f, "Julia set for c = ({}, {}) ", re, im)?;
+        }
+        match self.formula {
+            FormulaType::Multibrot => write!(f, "{} {} ", self.formula, self.power)?,
+            FormulaType::Phoenix => write!(f, "{} {} ", self.formula, self.phoenix_p)?,
+            formula => write!(f, "{} ", formula)?,
+        }
+        if self.rotation != 0.0 {
+            write!(f, "rotated {} degrees ", self.rotation)?;
+        }
         write!(
             f,
-            "Position ({}, {}) with scale {} and {} iterations at size {}x{} {} samples per pixel {} threads and colour code {}",
+            "Position ({}, {}) with scale {} and {} iterations at size {}x{} {} samples per pixel {} threads {} precision and {} colouring with {}",
             self.centrex,
             self.centrey,
             self.scaley,
@@ -68,79 +392,509 @@ impl fmt::Display for Options {
             self.height,
             self.samples * self.samples,
             self.threads,
-            self.colour
+            self.precision.resolve(self.sample_size()).0,
+            self.colouring,
+            colour
         )
     }
 }
 
+//Result for one pixel, averaged over its samples. Samples inside the set count as zero
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Pixel {
+    pub iterations: u32,
+    //Normalised iteration count
+    pub smooth: f32,
+    //|z| when the sample escaped
+    pub magnitude: f32,
+    pub colour: u32,
+}
+
+//Per pixel values only distance colouring, lighting and traps need. These are kept apart from
+//Pixel and only worked out and stored when Options::uses_extras says so, elsewhere the buffers
+//holding them stay empty
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PixelExtras {
+    //Estimated distance to the set in pixels and the direction of z / dz turned to the image
+    pub distance: f32,
+    pub normal: [f32; 2],
+    //Closest the orbit came to the trap and for image traps the colour it picked up
+    pub trap: f32,
+    pub texture: u32,
+}
+
+//One step of an escape time fractal. The worker, colouring and output code only see this,
+//so adding a fractal is just another implementation plus an entry in FormulaType
+pub trait Formula {
+    //Set for formulas that need z_n-1, saves tracking it for the ones that don't
+    const USES_PREVIOUS: bool = false;
+
+    //z_n+1 from z_n and c. squares holds x^2 and y^2 of z_n which the escape check already
+    //needed, and previous is z_n-1 (zero on the first step)
+    fn step<R: Real>(&self, z: (R, R), squares: (R, R), previous: &(R, R), c: &(R, R)) -> (R, R);
+
+    //Cheap test for points of the Mandelbrot type set that are known to never escape
+    fn known_interior<R: Real>(&self, _c: &(R, R)) -> bool {
+        false
+    }
+}
+
+pub fn mandelbrot(options: Options, sender: Sender<Tile>, scheduler: Arc<TileScheduler>) {
+    match options.formula {
+        FormulaType::Mandelbrot => {
+            let (precision, bits) = options.precision.resolve(options.sample_size());
+            //The vectorised kernel doesn't follow traps or carry the derivative
+            if options.simd
+                && options.active_trap().is_none()
+                && !options.uses_derivative()
+                && simd::supported(precision)
+            {
+                simd::mandelbrot(&options, precision, bits, sender, scheduler)
+            } else {
+                mandelbrot_formula(&options, &formula::Mandelbrot, sender, scheduler)
+            }
+        }
+        FormulaType::Multibrot => {
+            let formula = formula::Multibrot {
+                power: options.power,
+            };
+            mandelbrot_formula(&options, &formula, sender, scheduler)
+        }
+        FormulaType::BurningShip => {
+            mandelbrot_formula(&options, &formula::BurningShip, sender, scheduler)
+        }
+        FormulaType::Tricorn => mandelbrot_formula(&options, &formula::Tricorn, sender, scheduler),
+        FormulaType::Celtic => mandelbrot_formula(&options, &formula::Celtic, sender, scheduler),
+        FormulaType::Phoenix => {
+            let formula = formula::Phoenix {
+                p: options.phoenix_p,
+            };
+            mandelbrot_formula(&options, &formula, sender, scheduler)
+        }
+    }
+}
+
+fn mandelbrot_formula<F: Formula>(
+    options: &Options,
+    formula: &F,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+) {
+    let (precision, bits) = options.precision.resolve(options.sample_size());
+    match precision {
+        Precision::Single => mandelbrot_with::<f32, F>(options, formula, bits, sender, scheduler),
+        Precision::Double => mandelbrot_with::<f64, F>(options, formula, bits, sender, scheduler),
+        Precision::DoubleDouble => {
+            mandelbrot_with::<DoubleDouble, F>(options, formula, bits, sender, scheduler)
+        }
+        Precision::Arbitrary | Precision::Auto => {
+            mandelbrot_with::<BigFixed, F>(options, formula, bits, sender, scheduler)
+        }
+    }
+}
+
+//Iterates the formula from z. Returns the iteration count along with |z|^2 at the point the
+//loop stopped. With periodicity set the orbit is checked for cycles, Brent style: z is saved
+//at every power of two iterations and compared against each z after it. Only an exact repeat
+//counts so it can never stop a point that would have escaped. Each cycle found is added to
+//the counter. Every z checked against the bailout is shown to the trackers, and the
+//derivative is given z where the orbit escaped
 #[inline]
-fn iterations2colour(options: &Options, iter: u32, max_iter: u32, flags: u32) -> u32 {
-    let iter = (iter * options.max_colours / max_iter) & (options.max_colours - 1);
-    return (((flags & 4) << 14) | ((flags & 2) << 7) | (flags & 1)) * iter;
+pub(crate) fn escape_time<R: Real, F: Formula>(
+    formula: &F,
+    mut z: (R, R),
+    c: &(R, R),
+    zero: &R,
+    limits: (u32, f64),
+    mut periodicity: Option<&mut u64>,
+    trackers: &mut Trackers,
+) -> (u32, f64) {
+    let (max_iter, bailout) = limits;
+    let mut iter: u32 = 0;
+    let mut previous = (zero.clone(), zero.clone());
+    let mut norm = 0.0;
+    //Formulas using z_n-1 can revisit z without repeating, so they never check
+    let check_period = periodicity.is_some() && !F::USES_PREVIOUS;
+    let mut saved = z.clone();
+    let mut save_at: u32 = 1;
+    while iter <= max_iter {
+        let x2 = z.0.clone() * z.0.clone();
+        let y2 = z.1.clone() * z.1.clone();
+        norm = (x2.clone() + y2.clone()).to_f64();
+        if norm >= bailout {
+            if let Some(derivative) = trackers.derivative.as_mut() {
+                derivative.escaped = Some((z.0.to_f64(), z.1.to_f64()));
+            }
+            break;
+        }
+        trackers.visit(&z);
+        if F::USES_PREVIOUS {
+            let next = formula.step(z.clone(), (x2, y2), &previous, c);
+            previous = z;
+            z = next;
+        } else {
+            z = formula.step(z, (x2, y2), &previous, c);
+        }
+        iter += 1;
+
+        if check_period {
+            if z == saved {
+                if let Some(count) = periodicity.as_mut() {
+                    **count += 1;
+                }
+                return (max_iter + 1, norm);
+            }
+            if iter == save_at {
+                saved = z.clone();
+                save_at = save_at.saturating_mul(2);
+            }
+        }
+    }
+    (iter, norm)
 }
 
-fn interlocked_increment(shared: Arc<Mutex<u32>>) -> u32 {
-    let mut current = shared.lock().unwrap();
-    let temp = *current;
-    *current += 1;
-    temp
+//What a worker found for one sample. Anything over max_iter counts as inside the set
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sample {
+    pub iterations: u32,
+    //|z|^2 where the loop stopped
+    pub norm: f64,
+    //From the derivative, zero unless it was carried along or the sample never escaped
+    pub distance: f64,
+    //Unit vector along z / dz
+    pub normal: (f64, f64),
+    //Closest the orbit came to the trap and the image colour it found, if traps were followed
+    pub trap: f64,
+    pub texel: Option<[f64; 3]>,
 }
 
-pub fn mandelbrot(options: Options, sender: Sender<(u32, u32)>, current_line: Arc<Mutex<u32>>) {
-    let scalex: f32 = options.scaley * options.width as f32 / options.height as f32;
-    let colour: u32;
-    if options.colourise {
-        colour = options.thread_id.unwrap() % 7 + 1;
-    } else {
-        colour = options.colour;
+impl Sample {
+    pub fn new(iterations: u32, norm: f64) -> Sample {
+        Sample {
+            iterations,
+            norm,
+            ..Sample::default()
+        }
+    }
+
+    //Sample with whatever the trackers found along its orbit
+    pub(crate) fn with_trackers(self, trackers: Trackers) -> Sample {
+        let sample = match trackers
+            .derivative
+            .and_then(|d| d.escaped.map(|z| (z, d.dz)))
+        {
+            Some((z, dz)) => Sample::escaped(self.iterations, z, dz),
+            None => self,
+        };
+        match trackers.trap {
+            Some(tracker) => Sample {
+                trap: tracker.distance,
+                texel: tracker.texel,
+                ..sample
+            },
+            None => sample,
+        }
+    }
+
+    //Sample that escaped at z with derivative dz
+    pub fn escaped(iterations: u32, z: (f64, f64), dz: (f64, f64)) -> Sample {
+        let norm = z.0 * z.0 + z.1 * z.1;
+        //z / dz points the same way as z times the conjugate of dz
+        let u = (z.0 * dz.0 + z.1 * dz.1, z.1 * dz.0 - z.0 * dz.1);
+        let length = u.0.hypot(u.1);
+        Sample {
+            iterations,
+            norm,
+            distance: colour::distance_estimate(norm, dz.0 * dz.0 + dz.1 * dz.1),
+            normal: if length > 0.0 && length.is_finite() {
+                (u.0 / length, u.1 / length)
+            } else {
+                (0.0, 0.0)
+            },
+            ..Sample::default()
+        }
+    }
+}
+
+//Carries dz/dc along a Mandelbrot orbit, or dz/dz_0 for Julia sets, for the distance estimate
+//and lighting. Only the Mandelbrot formula has one, which validate makes sure of. The
+//derivative only needs f64
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Derivative {
+    pub dz: (f64, f64),
+    dc: f64,
+    //z where the orbit escaped, if it did
+    pub escaped: Option<(f64, f64)>,
+}
+
+impl Derivative {
+    pub fn new(julia: bool) -> Derivative {
+        Derivative {
+            dz: if julia { (1.0, 0.0) } else { (0.0, 0.0) },
+            dc: if julia { 0.0 } else { 1.0 },
+            escaped: None,
+        }
+    }
+
+    //dz_n+1 = 2 z_n dz_n + 1, without the 1 for Julia sets
+    #[inline]
+    pub fn step(&mut self, x: f64, y: f64) {
+        let dz = self.dz;
+        self.dz = (
+            2.0 * (x * dz.0 - y * dz.1) + self.dc,
+            2.0 * (x * dz.1 + y * dz.0),
+        );
+    }
+}
+
+//What gets followed along an orbit besides the iteration count, each only when the colouring
+//needs it
+#[derive(Default)]
+pub(crate) struct Trackers<'a> {
+    pub trap: Option<TrapTracker<'a>>,
+    pub derivative: Option<Derivative>,
+}
+
+impl<'a> Trackers<'a> {
+    pub fn new(options: &'a Options, julia: bool) -> Trackers<'a> {
+        Trackers {
+            trap: options.active_trap().map(Trap::tracker),
+            derivative: if options.uses_derivative() {
+                Some(Derivative::new(julia))
+            } else {
+                None
+            },
+        }
+    }
+
+    #[inline]
+    fn visit<R: Real>(&mut self, z: &(R, R)) {
+        if self.trap.is_none() && self.derivative.is_none() {
+            return;
+        }
+        let (x, y) = (z.0.to_f64(), z.1.to_f64());
+        if let Some(trap) = self.trap.as_mut() {
+            trap.visit(x, y);
+        }
+        if let Some(derivative) = self.derivative.as_mut() {
+            derivative.step(x, y);
+        }
     }
+}
+
+fn mandelbrot_with<R: Real, F: Formula>(
+    options: &Options,
+    formula: &F,
+    bits: u32,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+) {
+    let bailout = options.escape_radius * options.escape_radius;
+    let zero = R::from_f64(0.0, bits);
 
-    let dx: f32 = scalex / options.width as f32 / options.samples as f32;
-    let dy: f32 = options.scaley / options.height as f32 / options.samples as f32;
+    //Pixel positions are worked out as small offsets from the centre in f64 and only then
+    //added to the centre in the working precision, so deep zooms keep every bit of the centre
+    let centrex = R::from_fixed(&options.centrex, bits);
+    let centrey = R::from_fixed(&options.centrey, bits);
+    let julia = options
+        .julia
+        .as_ref()
+        .map(|(re, im)| (R::from_fixed(re, bits), R::from_fixed(im, bits)));
+    let limits = (options.max_iter, bailout);
+    //Points inside the set still have orbits to trap, so none are skipped
+    let cardioid_check = options.cardioid_check && options.active_trap().is_none();
 
-    let startx = options.centrex - scalex * 0.5;
-    let starty = options.centrey - options.scaley * 0.5;
-    let mut iy = interlocked_increment(current_line.clone());
+    render_tiles(options, sender, scheduler, |offsetx, offsety, stats| {
+        let x0 = centrex.clone() + R::from_f64(offsetx, bits);
+        let y0 = centrey.clone() + R::from_f64(offsety, bits);
+        let pixel = (x0, y0);
+        let periodicity = if options.periodicity_check {
+            Some(&mut stats.periodicity)
+        } else {
+            None
+        };
+        let mut trackers = Trackers::new(options, julia.is_some());
+        let (iter, norm) = match &julia {
+            //Julia sets start at the pixel and add the same constant every time
+            Some(c) => escape_time(formula, pixel, c, &zero, limits, periodicity, &mut trackers),
+            None => {
+                if cardioid_check && formula.known_interior(&pixel) {
+                    stats.cardioid += 1;
+                    return Sample::new(options.max_iter + 1, 0.0);
+                }
+                escape_time(
+                    formula,
+                    pixel.clone(),
+                    &pixel,
+                    &zero,
+                    limits,
+                    periodicity,
+                    &mut trackers,
+                )
+            }
+        };
+        Sample::new(iter, norm).with_trackers(trackers)
+    });
+}
+
+//Shared tile loop for the workers. sample is given the offset of a sample from the centre
+//and returns what it found there. Samples settled early by an interior test are counted in
+//the stats for the tile
+pub(crate) fn render_tiles<F: FnMut(f64, f64, &mut RenderStats) -> Sample>(
+    options: &Options,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+    mut sample: F,
+) {
+    render_tiles_batched(options, sender, scheduler, |offsets, results, stats| {
+        for (offset, result) in offsets.iter().zip(results.iter_mut()) {
+            *result = sample(offset.0, offset.1, stats);
+        }
+    });
+}
 
-    while iy < options.height {
-        for ix in 0..options.width {
-            let mut totaliter: u32 = 0;
+//Same as render_tiles but hands over every sample in a row of the tile at once, so kernels
+//that work on several samples together get full batches
+pub(crate) fn render_tiles_batched<F: FnMut(&[(f64, f64)], &mut [Sample], &mut RenderStats)>(
+    options: &Options,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+    mut sample_batch: F,
+) {
+    let scalex: f64 = options.scaley * options.width as f64 / options.height as f64;
+    let thread_id = options.thread_id.unwrap_or(0);
+    let colour: u32 = if options.colourise {
+        thread_id % 7 + 1
+    } else {
+        options.colour
+    };
 
-            for itery in 0..options.samples {
-                for iterx in 0..options.samples {
-                    let mut iter: u32 = 0;
+    let dx: f64 = scalex / options.width as f64 / options.samples as f64;
+    let dy: f64 = options.scaley / options.height as f64 / options.samples as f64;
+    let pixel_size = options.scaley / options.height as f64;
 
-                    let x0: f32 = startx + (ix as f32 * options.samples as f32 + iterx as f32) * dx;
-                    let y0: f32 = starty + (iy as f32 * options.samples as f32 + itery as f32) * dy;
-                    let mut x: f32 = x0;
-                    let mut y: f32 = y0;
-                    let mut xtemp: f32;
-                    while x * x + y * y < (2 * 2) as f32 && iter <= options.max_iter {
-                        xtemp = x * x - y * y + x0 as f32;
+    let halfx = (options.width * options.samples) as f64 * 0.5;
+    let halfy = (options.height * options.samples) as f64 * 0.5;
+    let count = options.samples * options.samples;
+    let (sin, cos) = options.rotation.to_radians().sin_cos();
+    let uses_extras = options.uses_extras();
 
-                        y = 2.0 * x * y + y0 as f32;
-                        x = xtemp;
-                        iter += 1;
+    let mut offsets: Vec<(f64, f64)> = Vec::new();
+    let mut results: Vec<Sample> = Vec::new();
+    while let Some(rect) = scheduler.claim() {
+        let mut pixels = Vec::with_capacity((rect.width * rect.height) as usize);
+        let mut extras = Vec::new();
+        let mut stats = RenderStats::default();
+        for iy in rect.y..rect.y + rect.height {
+            offsets.clear();
+            for ix in rect.x..rect.x + rect.width {
+                for itery in 0..options.samples {
+                    let offsety = ((iy * options.samples + itery) as f64 - halfy) * dy;
+                    for iterx in 0..options.samples {
+                        let offsetx = ((ix * options.samples + iterx) as f64 - halfx) * dx;
+                        if options.rotation == 0.0 {
+                            offsets.push((offsetx, offsety));
+                        } else {
+                            offsets.push((
+                                offsetx * cos - offsety * sin,
+                                offsetx * sin + offsety * cos,
+                            ));
+                        }
                     }
+                }
+            }
+            results.resize(offsets.len(), Sample::default());
+            sample_batch(&offsets, &mut results, &mut stats);
 
+            for samples in results.chunks(count as usize) {
+                let mut totaliter: u32 = 0;
+                let mut totalsmooth: f64 = 0.0;
+                let mut totalmagnitude: f64 = 0.0;
+                for sample in samples {
+                    let iter = sample.iterations;
                     if iter <= options.max_iter {
                         totaliter += iter;
+                        totalsmooth +=
+                            colour::smooth_iterations(iter, sample.norm, options.escape_radius);
+                        totalmagnitude += sample.norm.sqrt();
                     }
                 }
+                let mut pixel = Pixel {
+                    iterations: totaliter / count,
+                    smooth: (totalsmooth / count as f64) as f32,
+                    magnitude: (totalmagnitude / count as f64) as f32,
+                    colour: 0,
+                };
+                let pixel_extras = if uses_extras {
+                    average_extras(samples, options.max_iter, (sin, cos), pixel_size)
+                } else {
+                    PixelExtras::default()
+                };
+                pixel.colour = colour::colour_pixel(options, &pixel, &pixel_extras, colour);
+                pixels.push(pixel);
+                if uses_extras {
+                    extras.push(pixel_extras);
+                }
             }
+        }
+
+        let tile = Tile {
+            rect,
+            pixels,
+            extras,
+            thread_id,
+            stats,
+        };
+        //Nobody is waiting for tiles any more, so there is no point rendering the rest
+        if sender.send(tile).is_err() {
+            return;
+        }
+    }
+}
 
-            sender
-                .send((
-                    iy * options.width + ix,
-                    iterations2colour(
-                        &options,
-                        totaliter / (options.samples * options.samples),
-                        options.max_iter,
-                        colour,
-                    ),
-                ))
-                .unwrap();
-        }
-        iy = interlocked_increment(current_line.clone());
+//PixelExtras for one pixel from its samples. rotation is the sine and cosine of the view
+//rotation and pixel_size the height of a pixel in the complex plane
+fn average_extras(
+    samples: &[Sample],
+    max_iter: u32,
+    rotation: (f64, f64),
+    pixel_size: f64,
+) -> PixelExtras {
+    let count = samples.len() as f64;
+    let mut totaldistance: f64 = 0.0;
+    let mut totalnormal: (f64, f64) = (0.0, 0.0);
+    //Traps count every sample, inside the set or not
+    let mut totaltrap: f64 = 0.0;
+    let mut totaltexel = [0.0; 3];
+    for sample in samples {
+        totaltrap += sample.trap;
+        if let Some(texel) = sample.texel {
+            for (total, channel) in totaltexel.iter_mut().zip(texel) {
+                *total += channel;
+            }
+        }
+        if sample.iterations <= max_iter {
+            totaldistance += sample.distance;
+            totalnormal.0 += sample.normal.0;
+            totalnormal.1 += sample.normal.1;
+        }
+    }
+    //Turned back by the rotation so the light stays put on screen
+    let (sin, cos) = rotation;
+    let normal = (
+        totalnormal.0 * cos + totalnormal.1 * sin,
+        totalnormal.1 * cos - totalnormal.0 * sin,
+    );
+    let length = normal.0.hypot(normal.1);
+    PixelExtras {
+        //In pixels so it still fits an f32 at any zoom
+        distance: (totaldistance / count / pixel_size) as f32,
+        normal: if length > 0.0 {
+            [(normal.0 / length) as f32, (normal.1 / length) as f32]
+        } else {
+            [0.0, 0.0]
+        },
+        trap: (totaltrap / count) as f32,
+        texture: palette::pack(totaltexel.map(|total| total / count)),
     }
 }
@@ -1,63 +1,451 @@
-use argparse::{ArgumentParser, Store, StoreTrue};
-use image::{ImageBuffer, RgbImage};
-use mandelbrot::Options;
+use argparse::{ArgumentParser, List, Store, StoreFalse, StoreOption, StoreTrue};
+use image::RgbImage;
+use mandelbrot::checkpoint::{self, Checkpoint, CheckpointWriter};
+use mandelbrot::config::Preset;
+use mandelbrot::pyramid::{MAX_LEVELS, TILE_SIZE};
+use mandelbrot::{
+    colour, config, data, metadata, renderer, AnimatedWriter, Animation, BigFixed, ColourMode,
+    Error, FormulaType, Keyframes, Options, Palette, PngStream, Pyramid, RenderResult, Renderer,
+    Tile, TileServer, View, Zoom,
+};
 use pbr::ProgressBar;
-use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Instant;
+use std::fs;
+use std::io::{stderr, stdout, Stdout};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 const DEFAULT_MAX_COLOURS: u32 = 256;
 const DEFAULT_WIDTH: u32 = 1024;
 const DEFAULT_HEIGHT: u32 = 1024;
 const DEFAULT_MAX_ITER: u32 = 256;
-const DEFAULT_CENTREX: f32 = -0.75;
-const DEFAULT_CENTREY: f32 = 0.0;
-const DEFAULT_SCALEY: f32 = 2.5;
+const DEFAULT_CENTREX: f64 = -0.75;
+const DEFAULT_CENTREY: f64 = 0.0;
+const DEFAULT_SCALEY: f64 = 2.5;
 const DEFAULT_SAMPLES: u32 = 1;
 const DEFAULT_THREADS: u32 = 1;
 const DEFAULT_FILENAME: &str = "output.bmp";
+const DEFAULT_FRAME_DIR: &str = ".";
+const DEFAULT_FRAME_DELAY: u32 = 40;
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 30;
+const DEFAULT_BAND_HEIGHT: u32 = 256;
+const DEFAULT_PYRAMID_LEVELS: u32 = 4;
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_TILE_CACHE: usize = 1024;
 const DEFAULT_COLOUR_CODE: u32 = 7;
 const DEFAULT_COLOURISE: bool = false;
 const DEFAULT_PROGRESS: bool = false;
 
-fn generate(options: Options, out: &mut Vec<u32>) {
+fn progress_bar(total: u32, show: bool) -> ProgressBar<Stdout> {
+    let mut pb = ProgressBar::new(total as u64);
+    pb.show_bar = show;
+    pb.show_counter = show;
+    pb.show_message = show;
+    pb.show_percent = show;
+    pb.show_speed = false;
+    pb.show_time_left = false;
+    pb.show_tick = false;
+    pb
+}
+
+//Reports the error and exits with its code
+fn fail(e: Error) -> ! {
+    eprintln!("Error: {}", e);
+    std::process::exit(e.exit_code());
+}
+
+//fail for errors after the checkpoint was started, it is kept for --resume so everything added
+//to it gets written out first
+fn fail_keeping(checkpoint: &mut Option<CheckpointWriter>, e: Error) -> ! {
+    if let Some(Err(flush)) = checkpoint.as_mut().map(CheckpointWriter::flush) {
+        eprintln!("Error: {}", flush);
+    }
+    fail(e)
+}
+
+//PNGs get the settings written into them so they can be rendered again with --from-image
+fn save_image(filename: &str, image: &RgbImage, options: &Options) -> Result<(), Error> {
+    let png = Path::new(filename)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if png {
+        metadata::save_png(filename, image, options)
+    } else {
+        image.save(filename).map_err(|e| Error::image(filename, e))
+    }
+}
+
+//Renders one image. Tiles in done are already finished, every new tile goes to the checkpoint
+fn generate(
+    options: &Options,
+    done: &[Tile],
+    mut checkpoint: Option<&mut CheckpointWriter>,
+) -> Result<RenderResult, Error> {
     println!("{}", options);
     let start = Instant::now();
-    let current_line = Arc::new(Mutex::new(0));
-    let (tx, rx) = mpsc::channel();
 
-    for i in 0..options.threads {
-        let mut local_options = options;
-        local_options.thread_id = Some(i);
-        let local_tx = mpsc::Sender::clone(&tx);
-        let current_line_ref = Arc::clone(&current_line);
-        thread::spawn(move || mandelbrot::mandelbrot(local_options, local_tx, current_line_ref));
+    if options.perturbation && options.formula != FormulaType::Mandelbrot {
+        eprintln!(
+            "Perturbation only supports the mandelbrot formula, rendering {} directly",
+            options.formula
+        );
     }
 
-    //Drop tx because we only need it for cloning and if we don't drop it the loop below will never end
-    drop(tx);
-
-    let mut pb = ProgressBar::new(100);
-    pb.show_bar = options.progress;
-    pb.show_counter = options.progress;
-    pb.show_message = options.progress;
-    pb.show_percent = options.progress;
-    pb.show_speed = false;
-    pb.show_time_left = false;
-    pb.show_tick = false;
-    let mut pos = 0;
-    for (i, val) in rx {
-        pos += 1;
-        if pos % (options.width * options.height / 100) == 0 {
-            pb.inc();
+    let renderer = Renderer::new(options.clone())?;
+    let mut pb = progress_bar(renderer.tile_count(), options.progress);
+    pb.add(done.len() as u64);
+    let result = renderer.resume_with(done, |tile| {
+        pb.inc();
+        if let Some(writer) = checkpoint.as_mut() {
+            if let Err(e) = writer.add(tile) {
+                eprintln!("Error: {}, no more checkpoints will be saved", e);
+                checkpoint = None;
+            }
         }
-        out[i as usize] = val;
+    })?;
+    pb.finish_print("done");
+
+    if let Some((len, skipped)) = result.reference {
+        println!(
+            "reference orbit: {} iterations, {} skipped by series approximation",
+            len, skipped
+        );
     }
+    println!(
+        "interior: {} samples skipped by cardioid/bulb test, {} by periodicity checking",
+        result.stats.cardioid, result.stats.periodicity
+    );
+    println!("time taken: {}ms", start.elapsed().as_millis());
+    Ok(result)
+}
+
+//Renders straight into a PNG a band at a time so the whole image is never in memory
+fn stream(options: &Options, filename: &str, band_height: u32) -> Result<(), Error> {
+    println!("{}", options);
+    let start = Instant::now();
+
+    let renderer = Renderer::new(options.clone())?;
+    let band_height = band_height.div_ceil(options.tile_size).max(1) * options.tile_size;
+    let mut pb = progress_bar(options.height.div_ceil(band_height), options.progress);
+    let mut png = PngStream::create(
+        filename,
+        options.width,
+        options.height,
+        &metadata::text_chunks(options),
+    )?;
+    let stats = renderer.render_bands(band_height, |pixels| {
+        pb.inc();
+        png.write_rows(pixels)
+    })?;
+    png.finish()?;
     pb.finish_print("done");
 
-    //mandelbrot::mandelbrot(options, out);
+    println!(
+        "interior: {} samples skipped by cardioid/bulb test, {} by periodicity checking",
+        stats.cardioid, stats.periodicity
+    );
+    println!("time taken: {}ms", start.elapsed().as_millis());
+    Ok(())
+}
+
+fn pyramid(options: Options, dir: &str, levels: u32) -> Result<(), Error> {
+    println!("{}", options);
+    let start = Instant::now();
+
+    let pyramid = Pyramid {
+        options,
+        levels: levels.max(1),
+    };
+    let mut level_start = Instant::now();
+    pyramid.export(dir, |zoom, tiles| {
+        println!(
+            "level {}: {} tiles in {}ms",
+            zoom,
+            tiles,
+            level_start.elapsed().as_millis()
+        );
+        level_start = Instant::now();
+    })?;
+    println!("time taken: {}ms", start.elapsed().as_millis());
+    Ok(())
+}
+
+//Where animation frames go
+enum FrameOutput {
+    //Numbered PNG files in a directory
+    Directory(String),
+    //One animated GIF or APNG file
+    File(AnimatedWriter),
+}
+
+impl FrameOutput {
+    fn new(
+        animation_file: Option<String>,
+        directory: &str,
+        delay_ms: u32,
+    ) -> Result<FrameOutput, Error> {
+        match animation_file {
+            Some(path) => AnimatedWriter::create(&path, delay_ms).map(FrameOutput::File),
+            None => fs::create_dir_all(directory)
+                .map(|_| FrameOutput::Directory(String::from(directory)))
+                .map_err(|e| Error::io(directory, e)),
+        }
+    }
+
+    fn write(&mut self, frame: u32, image: &RgbImage) -> Result<(), Error> {
+        match self {
+            FrameOutput::Directory(directory) => {
+                let path = Path::new(directory).join(format!("frame_{:05}.png", frame + 1));
+                image
+                    .save(&path)
+                    .map_err(|e| Error::image(&path.to_string_lossy(), e))
+            }
+            FrameOutput::File(writer) => writer.add_frame(image),
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            FrameOutput::File(writer) => writer.finish(),
+            FrameOutput::Directory(_) => Ok(()),
+        }
+    }
+}
+
+//Renders every frame of the animation with one renderer so the worker threads are only started once
+fn animate(
+    options: &Options,
+    animation: &dyn Animation,
+    mut output: FrameOutput,
+) -> Result<(), Error> {
+    println!("{}", options);
+    let start = Instant::now();
+
+    let mut renderer = Renderer::new(options.clone())?;
+    for frame in 0..animation.frames() {
+        let frame_start = Instant::now();
+        renderer.set_options(animation.frame_options(options, frame))?;
+        let result = renderer.render()?;
+
+        output.write(frame, &result.image)?;
+        println!(
+            "frame {}/{}: scale {} in {}ms",
+            frame + 1,
+            animation.frames(),
+            renderer.options().scaley,
+            frame_start.elapsed().as_millis()
+        );
+    }
+    output.finish()?;
+
+    println!("time taken: {}ms", start.elapsed().as_millis());
+    Ok(())
+}
+
+//Palette cycling: the iterations are rendered once and every frame just colours them again with
+//the palette offset moved on by step
+fn cycle(options: &Options, frames: u32, step: f64, mut output: FrameOutput) -> Result<(), Error> {
+    let result = generate(options, &[], None)?;
+    let start = Instant::now();
+
+    let mut pixels = result.pixels;
+    let mut frame_options = options.clone();
+    for frame in 0..frames {
+        frame_options.palette_offset = options.palette_offset + step * frame as f64;
+        colour::recolour(&frame_options, &mut pixels, &result.extras);
+        output.write(
+            frame,
+            &renderer::to_image(&pixels, options.width, options.height),
+        )?;
+    }
+    output.finish()?;
+
+    println!(
+        "{} frames coloured in {}ms",
+        frames,
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+//Colour saved render data with different colouring options, no iterating needed
+fn recolour(args: Vec<String>) {
+    let mut input = String::new();
+    let mut filename = String::from(DEFAULT_FILENAME);
+    let mut colouring: Option<ColourMode> = None;
+    let mut palette: Option<Palette> = None;
+    let mut palette_offset: Option<f64> = None;
+    let mut colour: Option<u32> = None;
+    let mut light_angle: Option<f64> = None;
+    let mut light_height: Option<f64> = None;
+    {
+        let filename_text = format!(
+            "Set filename(default {}) supported formats are PNG, JPEG, BMP, and TIFF",
+            DEFAULT_FILENAME
+        );
+        let palette_text = format!(
+            "Use a gradient palette, either a palette file or one of {}",
+            mandelbrot::palette::BUILTIN_PALETTES.join(", ")
+        );
+
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Colour render data saved with --data again without rendering");
+        parser
+            .refer(&mut input)
+            .add_argument("data", Store, "Render data file")
+            .required();
+        parser
+            .refer(&mut filename)
+            .add_option(&["--name"], Store, &filename_text);
+        parser.refer(&mut colouring).add_option(
+            &["--colouring"],
+            StoreOption,
+            "Set colouring mode: iterations, smooth, distance or trap",
+        );
+        parser
+            .refer(&mut palette)
+            .add_option(&["--palette"], StoreOption, &palette_text);
+        parser.refer(&mut palette_offset).add_option(
+            &["--palette-offset"],
+            StoreOption,
+            "Shift the palette along by this fraction of a gradient",
+        );
+        parser.refer(&mut colour).add_option(
+            &["--colour"],
+            StoreOption,
+            "Use this colour code instead of a palette",
+        );
+        parser.refer(&mut light_angle).add_option(
+            &["--light-angle"],
+            StoreOption,
+            "Move the light, only for data rendered with --lighting",
+        );
+        parser.refer(&mut light_height).add_option(
+            &["--light-height"],
+            StoreOption,
+            "Raise or lower the light, only for data rendered with --lighting",
+        );
+
+        if let Err(code) = parser.parse(args, &mut stdout(), &mut stderr()) {
+            std::process::exit(code);
+        }
+    }
+
+    let start = Instant::now();
+    let (mut options, mut pixels, extras) = data::load(&input).unwrap_or_else(|e| fail(e));
+
+    //Anything not given on the command line stays as it was rendered
+    if let Some(colouring) = colouring {
+        options.colouring = colouring;
+    }
+    if let Some(offset) = palette_offset {
+        options.palette_offset = offset;
+    }
+    if let Some(colour) = colour {
+        options.colour = colour;
+        options.palette = None;
+    }
+    if palette.is_some() {
+        options.palette = palette;
+    }
+    if let Some(angle) = light_angle {
+        options.light_angle = angle;
+    }
+    if let Some(height) = light_height {
+        options.light_height = height;
+    }
+    println!("{}", options);
+
+    colour::recolour(&options, &mut pixels, &extras);
+    let img = renderer::to_image(&pixels, options.width, options.height);
     println!("time taken: {}ms", start.elapsed().as_millis());
+
+    save_image(&filename, &img, &options).unwrap_or_else(|e| fail(e));
+}
+
+//Value of an option that has to be known before the rest of the arguments are parsed
+fn early_option(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let mut value = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == name {
+            value = args.next().cloned();
+        } else if let Some(rest) = arg.strip_prefix(&prefix) {
+            value = Some(String::from(rest));
+        }
+    }
+    value
+}
+
+//Tile server for browsing in the bundled viewer
+fn serve(args: Vec<String>) {
+    let mut port = DEFAULT_PORT;
+    let mut cache_size = DEFAULT_TILE_CACHE;
+    let mut options = Options::new(
+        DEFAULT_MAX_COLOURS,
+        DEFAULT_MAX_ITER,
+        TILE_SIZE,
+        TILE_SIZE,
+        BigFixed::from(DEFAULT_CENTREX),
+        BigFixed::from(DEFAULT_CENTREY),
+        DEFAULT_SCALEY,
+        DEFAULT_SAMPLES,
+        DEFAULT_COLOUR_CODE,
+        DEFAULT_COLOURISE,
+        std::thread::available_parallelism().map_or(1, |n| n.get() as u32),
+        false,
+    );
+    {
+        let port_text = format!("Set port to listen on (default {})", DEFAULT_PORT);
+        let cache_text = format!(
+            "Set number of rendered tiles kept in memory (default {})",
+            DEFAULT_TILE_CACHE
+        );
+        let threads_text = format!(
+            "Set number of threads rendering each tile (default {})",
+            options.threads
+        );
+
+        let mut parser = ArgumentParser::new();
+        parser.set_description(
+            "Serve tiles rendered on demand and a viewer for them at http://localhost:PORT/. \
+             Tiles take settings in the query string with the same names as the options, \
+             e.g. /tiles/3/2/5.png?formula=burning-ship&palette=fire",
+        );
+        parser
+            .refer(&mut port)
+            .add_option(&["--port"], Store, &port_text);
+        parser
+            .refer(&mut cache_size)
+            .add_option(&["--cache-size"], Store, &cache_text);
+        parser
+            .refer(&mut options.threads)
+            .add_option(&["--threads", "-j"], Store, &threads_text);
+        parser.refer(&mut options.centrex).add_option(
+            &["--centrex"],
+            Store,
+            "Set centrex of the square shown at zoom level 0",
+        );
+        parser.refer(&mut options.centrey).add_option(
+            &["--centrey"],
+            Store,
+            "Set centrey of the square shown at zoom level 0",
+        );
+        parser.refer(&mut options.scaley).add_option(
+            &["--scale"],
+            Store,
+            "Set width and height of the square shown at zoom level 0",
+        );
+
+        if let Err(code) = parser.parse(args, &mut stdout(), &mut stderr()) {
+            std::process::exit(code);
+        }
+    }
+
+    println!("viewer at http://localhost:{}/", port);
+    TileServer::new(options, cache_size)
+        .and_then(|mut server| server.run(port))
+        .unwrap_or_else(|e| fail(e));
 }
 
 fn main() {
@@ -68,8 +456,8 @@ fn main() {
         DEFAULT_MAX_ITER,
         DEFAULT_WIDTH,
         DEFAULT_HEIGHT,
-        DEFAULT_CENTREX,
-        DEFAULT_CENTREY,
+        BigFixed::from(DEFAULT_CENTREX),
+        BigFixed::from(DEFAULT_CENTREY),
         DEFAULT_SCALEY,
         DEFAULT_SAMPLES,
         DEFAULT_COLOUR_CODE,
@@ -78,6 +466,52 @@ fn main() {
         DEFAULT_PROGRESS,
     );
 
+    //Config files and presets only change the starting values, so any flag given still wins
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(path) = config::user_config_path().filter(|path| path.exists()) {
+        config::load(&path.to_string_lossy(), &mut options).unwrap_or_else(|e| fail(e));
+    }
+    let mut config_file = early_option(&args, "--config");
+    if let Some(path) = &config_file {
+        config::load(path, &mut options).unwrap_or_else(|e| fail(e));
+    }
+    let mut preset = early_option(&args, "--preset");
+    if let Some(name) = &preset {
+        match Preset::named(name) {
+            Some(preset) => preset.apply(&mut options),
+            None => fail(Error::Options(format!(
+                "Unknown preset {}, the presets are {}",
+                name,
+                config::preset_names().join(", ")
+            ))),
+        }
+    }
+    let mut dump_config = false;
+
+    let mut julia_re: Option<BigFixed> = None;
+    let mut julia_im: Option<BigFixed> = None;
+    let mut data_file: Option<String> = None;
+    let mut from_image: Option<String> = None;
+    let mut frames: u32 = 0;
+    let mut end_centrex: Option<BigFixed> = None;
+    let mut end_centrey: Option<BigFixed> = None;
+    let mut end_scale: Option<f64> = None;
+    let mut frame_dir = String::from(DEFAULT_FRAME_DIR);
+    let mut keyframes_file: Option<String> = None;
+    let mut animation_file: Option<String> = None;
+    let mut frame_delay: Option<u32> = None;
+    let mut cycle_frames: u32 = 0;
+    let mut cycle_step: Option<f64> = None;
+    let mut save_checkpoint = false;
+    let mut resume = false;
+    let mut checkpoint_interval = DEFAULT_CHECKPOINT_INTERVAL;
+    let mut streaming = false;
+    let mut band_height = DEFAULT_BAND_HEIGHT;
+    let mut pyramid_dir: Option<String> = None;
+    let mut pyramid_levels = DEFAULT_PYRAMID_LEVELS;
+    let mut command = String::new();
+    let mut command_args: Vec<String> = Vec::new();
+
     //Handle command line arguments
     {
         //Using variables here because I wanted to format and parser takes a &str
@@ -94,9 +528,57 @@ fn main() {
             DEFAULT_MAX_ITER
         );
         let scaley_text = format!("Set scale(default {})", DEFAULT_SCALEY);
+        let rotation_text = format!(
+            "Turn the view about its centre by this many degrees (default {})",
+            options.rotation
+        );
         let samples_text = format!("Set samples for supersampling(default {})", DEFAULT_SAMPLES);
         let colour_text = format!("Set colour for image(default {})", DEFAULT_COLOUR_CODE);
+        let precision_text = format!(
+            "Set arithmetic precision: auto, single, double, double-double or arbitrary (default {})",
+            options.precision
+        );
+        let simd_text = "Disable the vectorised kernel for single and double precision Mandelbrot";
+        let perturbation_text = format!(
+            "Iterate pixels as deltas from a high precision reference orbit (default {})",
+            options.perturbation
+        );
+        let cardioid_text =
+            "Disable skipping points inside the main cardioid and period 2 bulb of the Mandelbrot set";
+        let periodicity_text = "Disable stopping early when the orbit of a point repeats";
+        let series_text = format!(
+            "Skip early iterations with series approximation when using perturbation (default {})",
+            options.series_approximation
+        );
+        let colouring_text = format!(
+            "Set colouring mode: iterations, smooth, distance or trap (default {})",
+            options.colouring
+        );
+        let palette_text = format!(
+            "Use a gradient palette instead of the colour code, either a palette file or one of {}",
+            mandelbrot::palette::BUILTIN_PALETTES.join(", ")
+        );
+        let palette_offset_text = format!(
+            "Shift the palette along by this fraction of a gradient, or of the colour code bands (default {})",
+            options.palette_offset
+        );
+        let escape_radius_text = format!(
+            "Set escape radius, larger values give smoother colouring (default {})",
+            options.escape_radius
+        );
+        let light_angle_text = format!(
+            "Set direction the light comes from with --lighting, in degrees anticlockwise from the right (default {})",
+            options.light_angle
+        );
+        let light_height_text = format!(
+            "Set height of the light above the image with --lighting, lower gives longer shadows (default {})",
+            options.light_height
+        );
         let progress_text = format!("Display progress bar (default {})", DEFAULT_PROGRESS);
+        let tile_size_text = format!(
+            "Set size of the square tiles handed to each thread (default {})",
+            options.tile_size
+        );
         let threads_text = format!(
             "Set number of threads to use for processing(default {})",
             DEFAULT_THREADS
@@ -106,6 +588,49 @@ fn main() {
             DEFAULT_FILENAME
         );
 
+        let frame_delay_text = format!(
+            "Set milliseconds each frame is shown for in an animation file (default {}, or 1000/fps for keyframes)",
+            DEFAULT_FRAME_DELAY
+        );
+        let checkpoint_interval_text = format!(
+            "Set seconds between checkpoint saves (default {})",
+            DEFAULT_CHECKPOINT_INTERVAL
+        );
+        let band_height_text = format!(
+            "Set rows rendered at a time when streaming, rounded up to whole tiles (default {})",
+            DEFAULT_BAND_HEIGHT
+        );
+        let pyramid_levels_text = format!(
+            "Set number of zoom levels in the tile pyramid, at most {} (default {})",
+            MAX_LEVELS, DEFAULT_PYRAMID_LEVELS
+        );
+        let preset_text = format!(
+            "Start from a named view, one of {}",
+            config::preset_names().join(", ")
+        );
+        let frame_dir_text = format!(
+            "Set directory animation frames are written to as frame_00001.png and so on (default {})",
+            DEFAULT_FRAME_DIR
+        );
+
+        let formula_text = format!(
+            "Set formula: {} (default {})",
+            mandelbrot::formula::FORMULAS.join(", "),
+            options.formula
+        );
+        let power_text = format!(
+            "Set exponent for the multibrot formula (default {})",
+            options.power
+        );
+        let phoenix_p_text = format!(
+            "Set weight of the previous z for the phoenix formula (default {})",
+            options.phoenix_p
+        );
+        let julia_re_text =
+            "Render the Julia set for this real part of c instead of the Mandelbrot set";
+        let julia_im_text =
+            "Render the Julia set for this imaginary part of c instead of the Mandelbrot set";
+
         let mut parser = ArgumentParser::new();
         parser.set_description("Mandelbrot generator");
         parser
@@ -122,21 +647,104 @@ fn main() {
         parser
             .refer(&mut options.centrey)
             .add_option(&["--centrey"], Store, &centrey_text);
+        parser
+            .refer(&mut options.formula)
+            .add_option(&["--formula"], Store, &formula_text);
+        parser
+            .refer(&mut options.power)
+            .add_option(&["--power"], Store, &power_text);
+        parser
+            .refer(&mut options.phoenix_p)
+            .add_option(&["--phoenix-p"], Store, &phoenix_p_text);
+        parser
+            .refer(&mut julia_re)
+            .add_option(&["--julia-re"], StoreOption, julia_re_text);
+        parser
+            .refer(&mut julia_im)
+            .add_option(&["--julia-im"], StoreOption, julia_im_text);
         parser
             .refer(&mut options.max_iter)
             .add_option(&["--iterations"], Store, &max_iter_text);
         parser
             .refer(&mut options.scaley)
             .add_option(&["--scale"], Store, &scaley_text);
+        parser
+            .refer(&mut options.rotation)
+            .add_option(&["--rotation"], Store, &rotation_text);
+        parser
+            .refer(&mut options.precision)
+            .add_option(&["--precision"], Store, &precision_text);
+        parser
+            .refer(&mut options.simd)
+            .add_option(&["--no-simd"], StoreFalse, simd_text);
+        parser.refer(&mut options.cardioid_check).add_option(
+            &["--no-cardioid-check"],
+            StoreFalse,
+            cardioid_text,
+        );
+        parser.refer(&mut options.periodicity_check).add_option(
+            &["--no-periodicity-check"],
+            StoreFalse,
+            periodicity_text,
+        );
+        parser.refer(&mut options.perturbation).add_option(
+            &["--perturbation"],
+            StoreTrue,
+            &perturbation_text,
+        );
+        parser.refer(&mut options.series_approximation).add_option(
+            &["--series-approximation"],
+            StoreTrue,
+            &series_text,
+        );
         parser
             .refer(&mut options.samples)
             .add_option(&["--samples"], Store, &samples_text);
         parser
             .refer(&mut options.colour)
             .add_option(&["--colour"], Store, &colour_text);
+        parser
+            .refer(&mut options.colouring)
+            .add_option(&["--colouring"], Store, &colouring_text);
+        parser
+            .refer(&mut options.palette)
+            .add_option(&["--palette"], StoreOption, &palette_text);
+        parser.refer(&mut options.palette_offset).add_option(
+            &["--palette-offset"],
+            Store,
+            &palette_offset_text,
+        );
+        parser.refer(&mut options.escape_radius).add_option(
+            &["--escape-radius"],
+            Store,
+            &escape_radius_text,
+        );
+        parser.refer(&mut options.trap).add_option(
+            &["--trap"],
+            StoreOption,
+            "Set orbit trap for trap colouring: point:X,Y, line:X,Y,ANGLE, cross:X,Y, circle:X,Y,RADIUS or image:X,Y,SIZE,FILE",
+        );
+        parser.refer(&mut options.lighting).add_option(
+            &["--lighting"],
+            StoreTrue,
+            "Shade the set as a lit embossed surface",
+        );
+        parser.refer(&mut options.light_angle).add_option(
+            &["--light-angle"],
+            Store,
+            &light_angle_text,
+        );
+        parser.refer(&mut options.light_height).add_option(
+            &["--light-height"],
+            Store,
+            &light_height_text,
+        );
         parser
             .refer(&mut options.threads)
             .add_option(&["--threads", "-j"], Store, &threads_text);
+        parser
+            .refer(&mut options.tile_size)
+            .add_option(&["--tile-size"], Store, &tile_size_text);
         parser
             .refer(&mut filename)
             .add_option(&["--name"], Store, &filename_text);
@@ -149,27 +757,271 @@ fn main() {
             .refer(&mut options.progress)
             .add_option(&["--progress"], StoreTrue, &progress_text);
 
+        parser.refer(&mut config_file).add_option(
+            &["--config"],
+            StoreOption,
+            "Load settings from this TOML file, after the user config file if there is one",
+        );
+        parser
+            .refer(&mut preset)
+            .add_option(&["--preset"], StoreOption, &preset_text);
+        parser.refer(&mut dump_config).add_option(
+            &["--dump-config"],
+            StoreTrue,
+            "Print the settings that would be used as a config file and stop",
+        );
+        parser.refer(&mut from_image).add_option(
+            &["--from-image"],
+            StoreOption,
+            "Render with the settings saved in a PNG made by this program, replacing the settings given here",
+        );
+        parser.refer(&mut data_file).add_option(
+            &["--data"],
+            StoreOption,
+            "Also save the iteration data to this file so it can be recoloured later",
+        );
+        parser.refer(&mut streaming).add_option(
+            &["--stream"],
+            StoreTrue,
+            "Write the image as a PNG a band of rows at a time, for images too big to fit in memory. --name has to end in .png",
+        );
+        parser
+            .refer(&mut band_height)
+            .add_option(&["--band-height"], Store, &band_height_text);
+        parser.refer(&mut pyramid_dir).add_option(
+            &["--pyramid"],
+            StoreOption,
+            "Write a tile pyramid of the square the height of the view into this directory as z/x/y.png tiles and pyramid.dzi",
+        );
+        parser.refer(&mut pyramid_levels).add_option(
+            &["--pyramid-levels"],
+            Store,
+            &pyramid_levels_text,
+        );
+        parser.refer(&mut save_checkpoint).add_option(
+            &["--checkpoint"],
+            StoreTrue,
+            "Save finished tiles next to the image as NAME.checkpoint so an interrupted render can be resumed",
+        );
+        parser.refer(&mut resume).add_option(
+            &["--resume"],
+            StoreTrue,
+            "Carry on an interrupted render from NAME.checkpoint with the settings saved in it",
+        );
+        parser.refer(&mut checkpoint_interval).add_option(
+            &["--checkpoint-interval"],
+            Store,
+            &checkpoint_interval_text,
+        );
+        parser.refer(&mut frames).add_option(
+            &["--frames"],
+            Store,
+            "Render a zoom animation with this many frames instead of a single image",
+        );
+        parser.refer(&mut end_centrex).add_option(
+            &["--end-centrex"],
+            StoreOption,
+            "Set centrex of the last animation frame (default centrex)",
+        );
+        parser.refer(&mut end_centrey).add_option(
+            &["--end-centrey"],
+            StoreOption,
+            "Set centrey of the last animation frame (default centrey)",
+        );
+        parser.refer(&mut end_scale).add_option(
+            &["--end-scale"],
+            StoreOption,
+            "Set scale of the last animation frame (default scale)",
+        );
+        parser.refer(&mut keyframes_file).add_option(
+            &["--keyframes"],
+            StoreOption,
+            "Render an animation following the camera path in this keyframe file",
+        );
+        parser.refer(&mut animation_file).add_option(
+            &["--animation"],
+            StoreOption,
+            "Write the animation frames into one looping file instead, animated GIF (.gif) or APNG (.png or .apng)",
+        );
+        parser.refer(&mut frame_delay).add_option(
+            &["--frame-delay"],
+            StoreOption,
+            &frame_delay_text,
+        );
+        parser
+            .refer(&mut frame_dir)
+            .add_option(&["--frame-dir"], Store, &frame_dir_text);
+        parser.refer(&mut cycle_frames).add_option(
+            &["--cycle-frames"],
+            Store,
+            "Render once then write this many animation frames with the palette shifted along each frame",
+        );
+        parser.refer(&mut cycle_step).add_option(
+            &["--cycle-step"],
+            StoreOption,
+            "Set how far the palette offset moves each palette cycling frame (default 1/frames, one seamless loop)",
+        );
+        parser.refer(&mut command).add_argument(
+            "command",
+            Store,
+            "Optional command to run instead of rendering: recolour or serve",
+        );
+        parser.refer(&mut command_args).add_argument(
+            "arguments",
+            List,
+            "Arguments for the command",
+        );
+        parser.stop_on_first_argument(true);
+
         parser.parse_args_or_exit();
     }
 
-    let mut buffer = vec![0; (options.width * options.height) as usize];
+    match command.as_str() {
+        "" => {}
+        "recolour" => {
+            command_args.insert(0, String::from("mandelbrot recolour"));
+            recolour(command_args);
+            return;
+        }
+        "serve" => {
+            command_args.insert(0, String::from("mandelbrot serve"));
+            serve(command_args);
+            return;
+        }
+        _ => {
+            eprintln!("Error: Unknown command {}", command);
+            std::process::exit(1);
+        }
+    }
+
+    //Either part on its own is enough to switch to Julia mode, the other defaults to zero or
+    //the config file's value
+    if julia_re.is_some() || julia_im.is_some() {
+        let (re, im) = options
+            .julia
+            .take()
+            .unwrap_or_else(|| (BigFixed::from(0.0), BigFixed::from(0.0)));
+        options.julia = Some((julia_re.unwrap_or(re), julia_im.unwrap_or(im)));
+    }
+
+    if let Some(path) = from_image {
+        let (saved, version) = metadata::load_png(&path).unwrap_or_else(|e| fail(e));
+        if version != metadata::VERSION {
+            eprintln!(
+                "{} was rendered by version {}, this is version {} so it may not match exactly",
+                path,
+                version,
+                metadata::VERSION
+            );
+        }
+        let progress = options.progress;
+        options = saved;
+        options.progress = progress;
+    }
+    options.validate().unwrap_or_else(|e| fail(e));
+
+    if dump_config {
+        print!("{}", config::to_toml(&options));
+        return;
+    }
+
+    if let Some(path) = keyframes_file {
+        let keyframes = Keyframes::load(&path, &options).unwrap_or_else(|e| fail(e));
+        println!(
+            "{} keyframes with {} interpolation, {} frames at {} fps",
+            keyframes.keyframes.len(),
+            keyframes.interpolation,
+            keyframes.frames(),
+            keyframes.fps
+        );
+        let delay = frame_delay.unwrap_or((1000.0 / keyframes.fps).round() as u32);
+        FrameOutput::new(animation_file, &frame_dir, delay)
+            .and_then(|output| animate(&options, &keyframes, output))
+            .unwrap_or_else(|e| fail(e));
+        return;
+    }
+
+    if cycle_frames > 0 {
+        let step = cycle_step.unwrap_or(1.0 / cycle_frames as f64);
+        println!(
+            "palette cycling over {} frames, offset step {}",
+            cycle_frames, step
+        );
+        let delay = frame_delay.unwrap_or(DEFAULT_FRAME_DELAY);
+        FrameOutput::new(animation_file, &frame_dir, delay)
+            .and_then(|output| cycle(&options, cycle_frames, step, output))
+            .unwrap_or_else(|e| fail(e));
+        return;
+    }
 
-    generate(options, &mut buffer);
+    if frames > 0 {
+        let zoom = Zoom {
+            start: View::from_options(&options),
+            end: View {
+                centrex: end_centrex.unwrap_or_else(|| options.centrex.clone()),
+                centrey: end_centrey.unwrap_or_else(|| options.centrey.clone()),
+                scale: end_scale.unwrap_or(options.scaley),
+            },
+            frames,
+        };
+        println!(
+            "zoom to ({}, {}) with scale {} over {} frames",
+            zoom.end.centrex, zoom.end.centrey, zoom.end.scale, zoom.frames
+        );
+        let delay = frame_delay.unwrap_or(DEFAULT_FRAME_DELAY);
+        FrameOutput::new(animation_file, &frame_dir, delay)
+            .and_then(|output| animate(&options, &zoom, output))
+            .unwrap_or_else(|e| fail(e));
+        return;
+    }
 
-    //Create a blank image to write to
-    let mut img: RgbImage = ImageBuffer::new(options.width, options.height);
+    if let Some(dir) = pyramid_dir {
+        pyramid(options, &dir, pyramid_levels).unwrap_or_else(|e| fail(e));
+        return;
+    }
 
-    for (x, y, pixel) in img.enumerate_pixels_mut() {
-        //32 bit number but only storing rgb so split it into its 3 8 bit components
-        let b =
-            ((buffer[y as usize * options.width as usize + x as usize] & 0x00ff0000) >> 16) as u8;
-        let g =
-            ((buffer[y as usize * options.width as usize + x as usize] & 0x0000ff00) >> 8) as u8;
-        let r = (buffer[y as usize * options.width as usize + x as usize] & 0x000000ff) as u8;
-        *pixel = image::Rgb([r, g, b]);
+    if streaming {
+        if data_file.is_some() || save_checkpoint || resume {
+            fail(Error::Options(String::from(
+                "--stream can't be used with --data, --checkpoint or --resume",
+            )));
+        }
+        stream(&options, &filename, band_height).unwrap_or_else(|e| fail(e));
+        return;
     }
 
-    img.save(&filename).unwrap_or_else(|_| {
-        eprintln!("Error: Could not write file");
-    });
+    let sidecar = checkpoint::sidecar_path(&filename);
+    let mut done = Vec::new();
+    if resume {
+        let checkpoint = Checkpoint::load(&sidecar).unwrap_or_else(|e| fail(e));
+        let progress = options.progress;
+        options = checkpoint.options;
+        options.progress = progress;
+        done = checkpoint.tiles;
+        println!("resuming from {} with {} tiles done", sidecar, done.len());
+    }
+
+    //Resuming starts the checkpoint afresh with the tiles it already had, which also drops any
+    //tile that was only half written
+    let mut writer = None;
+    if save_checkpoint || resume {
+        let interval = Duration::from_secs(checkpoint_interval);
+        let started = CheckpointWriter::create(&sidecar, &options, interval)
+            .and_then(|mut w| done.iter().try_for_each(|tile| w.add(tile)).map(|_| w));
+        writer = Some(started.unwrap_or_else(|e| fail(e)));
+    }
+
+    let result =
+        generate(&options, &done, writer.as_mut()).unwrap_or_else(|e| fail_keeping(&mut writer, e));
+
+    if let Some(path) = data_file {
+        data::save(&path, &options, &result.pixels, &result.extras)
+            .unwrap_or_else(|e| fail_keeping(&mut writer, e));
+    }
+
+    //The checkpoint is kept unless the image was saved
+    save_image(&filename, &result.image, &options).unwrap_or_else(|e| fail_keeping(&mut writer, e));
+    if let Some(writer) = writer {
+        writer.remove().unwrap_or_else(|e| fail(e));
+    }
 }
@@ -0,0 +1,95 @@
+use crate::animated::{chunks, write_chunk};
+use crate::{Error, Options};
+use image::png::PngEncoder;
+use image::{ColorType, RgbImage};
+use std::fs;
+use std::io::Write;
+
+//Settings are stored in PNG tEXt chunks, one per setting, with the setting name after this
+//prefix as the keyword. The version of the program that rendered the image goes in as well
+const PREFIX: &str = "mandelbrot:";
+const VERSION_KEY: &str = "version";
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+//Keyword and text of every chunk describing a render with these options
+pub fn text_chunks(options: &Options) -> Vec<(String, String)> {
+    let mut text = vec![
+        (String::from("Software"), format!("mandelbrot {}", VERSION)),
+        (format!("{}{}", PREFIX, VERSION_KEY), String::from(VERSION)),
+    ];
+    for (key, value) in options.to_params() {
+        text.push((format!("{}{}", PREFIX, key), value));
+    }
+    text
+}
+
+//tEXt chunk body, keyword and text are Latin-1 so anything else is replaced
+pub(crate) fn write_text<W: Write>(out: &mut W, keyword: &str, text: &str) -> std::io::Result<()> {
+    let latin1 = |s: &str| -> Vec<u8> {
+        s.chars()
+            .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+            .collect()
+    };
+    let mut body = latin1(keyword);
+    body.push(0);
+    body.extend(latin1(text));
+    write_chunk(out, b"tEXt", &body)
+}
+
+//Saves the image as a PNG with the settings that rendered it
+pub fn save_png(path: &str, image: &RgbImage, options: &Options) -> Result<(), Error> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(image, image.width(), image.height(), ColorType::Rgb8)
+        .map_err(|e| Error::image(path, e))?;
+
+    //The text goes straight after IHDR, before any image data
+    let error = |e: std::io::Error| Error::io(path, e);
+    let mut out = Vec::with_capacity(png.len() + 2048);
+    out.extend_from_slice(&png[..8]);
+    for (kind, body) in chunks(&png, path)? {
+        let kind = [kind[0], kind[1], kind[2], kind[3]];
+        write_chunk(&mut out, &kind, body).map_err(error)?;
+        if &kind == b"IHDR" {
+            for (keyword, text) in text_chunks(options) {
+                write_text(&mut out, &keyword, &text).map_err(error)?;
+            }
+        }
+    }
+    fs::write(path, out).map_err(error)
+}
+
+//Settings saved in a PNG by save_png, along with the version of the program that saved them
+pub fn load_png(path: &str) -> Result<(Options, String), Error> {
+    let png = fs::read(path).map_err(|e| Error::io(path, e))?;
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(Error::format(path, "not a PNG file"));
+    }
+
+    let mut params = Vec::new();
+    let mut version = String::new();
+    for (kind, body) in chunks(&png, path)? {
+        if kind != b"tEXt" {
+            continue;
+        }
+        let split = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+        let keyword: String = body[..split].iter().map(|&b| b as char).collect();
+        let text: String = body[(split + 1).min(body.len())..]
+            .iter()
+            .map(|&b| b as char)
+            .collect();
+        match keyword.strip_prefix(PREFIX) {
+            Some(VERSION_KEY) => version = text,
+            Some(key) => params.push((String::from(key), text)),
+            None => {}
+        }
+    }
+    if params.is_empty() {
+        return Err(Error::format(path, "no render settings saved in the image"));
+    }
+
+    let options = Options::from_params(params.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+        .map_err(|e| Error::format(path, e))?;
+    Ok((options, version))
+}
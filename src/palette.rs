@@ -0,0 +1,327 @@
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+//What happens to positions outside 0..1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PaletteMode {
+    //Wrap around so the gradient repeats
+    Cyclic,
+    //Hold the colour of the first or last stop
+    Clamped,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stop {
+    pub position: f64,
+    pub colour: [f64; 3],
+}
+
+//Multi-stop gradient. Palette files are plain text, one entry per line:
+//
+//  # comment
+//  mode cyclic            (or clamp)
+//  repeat 4               (gradient repeats this many times over max_iter)
+//  inside 0 0 0           (colour for pixels that never escaped)
+//  0.0 rgb 0 7 100
+//  0.5 hsv 60 1.0 1.0     (hue in degrees, saturation and value 0 to 1)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub mode: PaletteMode,
+    pub repeat: f64,
+    pub inside: [f64; 3],
+    pub stops: Vec<Stop>,
+}
+
+pub const BUILTIN_PALETTES: [&str; 5] = ["ultra", "fire", "ocean", "grey", "rainbow"];
+
+//Name given to a palette read back from saved settings rather than a file
+pub const SAVED_PALETTE: &str = "saved";
+
+fn hsv2rgb(hue: f64, saturation: f64, value: f64) -> [f64; 3] {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    [(r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0]
+}
+
+fn rgb(r: f64, g: f64, b: f64) -> [f64; 3] {
+    [r, g, b]
+}
+
+impl Palette {
+    pub fn new(name: &str, mode: PaletteMode, mut stops: Vec<Stop>) -> Palette {
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Palette {
+            name: String::from(name),
+            mode,
+            repeat: 1.0,
+            inside: [0.0; 3],
+            stops,
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Palette> {
+        let stop = |position, colour| Stop { position, colour };
+        let palette = match name {
+            "ultra" => Palette::new(
+                name,
+                PaletteMode::Cyclic,
+                vec![
+                    stop(0.0, rgb(0.0, 7.0, 100.0)),
+                    stop(0.16, rgb(32.0, 107.0, 203.0)),
+                    stop(0.42, rgb(237.0, 255.0, 255.0)),
+                    stop(0.6425, rgb(255.0, 170.0, 0.0)),
+                    stop(0.8575, rgb(0.0, 2.0, 0.0)),
+                ],
+            ),
+            "fire" => Palette::new(
+                name,
+                PaletteMode::Clamped,
+                vec![
+                    stop(0.0, rgb(0.0, 0.0, 0.0)),
+                    stop(0.3, rgb(180.0, 0.0, 0.0)),
+                    stop(0.6, rgb(255.0, 150.0, 0.0)),
+                    stop(1.0, rgb(255.0, 255.0, 200.0)),
+                ],
+            ),
+            "ocean" => Palette::new(
+                name,
+                PaletteMode::Cyclic,
+                vec![
+                    stop(0.0, rgb(0.0, 10.0, 40.0)),
+                    stop(0.4, rgb(0.0, 110.0, 160.0)),
+                    stop(0.7, rgb(120.0, 220.0, 230.0)),
+                ],
+            ),
+            "grey" => Palette::new(
+                name,
+                PaletteMode::Clamped,
+                vec![
+                    stop(0.0, rgb(0.0, 0.0, 0.0)),
+                    stop(1.0, rgb(255.0, 255.0, 255.0)),
+                ],
+            ),
+            "rainbow" => Palette::new(
+                name,
+                PaletteMode::Cyclic,
+                (0..6)
+                    .map(|i| stop(i as f64 / 6.0, hsv2rgb(i as f64 * 60.0, 1.0, 1.0)))
+                    .collect(),
+            ),
+            _ => return None,
+        };
+        Some(palette)
+    }
+
+    pub fn load(path: &str) -> Result<Palette, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Palette::parse(path, &text)
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Palette, String> {
+        let mut palette = Palette::new(name, PaletteMode::Clamped, Vec::new());
+        for (number, line) in text.lines().enumerate() {
+            let bad = || format!("{} line {}: could not parse {:?}", name, number + 1, line);
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let numbers = |from: usize| -> Result<Vec<f64>, String> {
+                words[from..]
+                    .iter()
+                    .map(|w| w.parse::<f64>().map_err(|_| bad()))
+                    .collect()
+            };
+            match words[0] {
+                "mode" => {
+                    palette.mode = match words.get(1) {
+                        Some(&"cyclic") => PaletteMode::Cyclic,
+                        Some(&"clamp") => PaletteMode::Clamped,
+                        _ => return Err(bad()),
+                    }
+                }
+                "repeat" => match numbers(1)?.as_slice() {
+                    [repeat] if *repeat > 0.0 => palette.repeat = *repeat,
+                    _ => return Err(bad()),
+                },
+                "inside" => match numbers(1)?.as_slice() {
+                    [r, g, b] => palette.inside = rgb(*r, *g, *b),
+                    _ => return Err(bad()),
+                },
+                position => {
+                    let position = position
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|p| p.is_finite())
+                        .ok_or_else(bad)?;
+                    let values = numbers(2)?;
+                    let colour = match (words.get(1), values.as_slice()) {
+                        (Some(&"rgb"), [r, g, b]) => rgb(*r, *g, *b),
+                        (Some(&"hsv"), [h, s, v]) => hsv2rgb(*h, *s, *v),
+                        _ => return Err(bad()),
+                    };
+                    palette.stops.push(Stop { position, colour });
+                }
+            }
+        }
+        if palette.stops.is_empty() {
+            return Err(format!("{}: palette has no colour stops", name));
+        }
+        palette
+            .stops
+            .sort_by(|a, b| a.position.total_cmp(&b.position));
+        Ok(palette)
+    }
+
+    //Whether this is one of the built in palettes, unchanged
+    pub fn is_builtin(&self) -> bool {
+        Palette::named(&self.name).as_ref() == Some(self)
+    }
+
+    //The whole palette in the file format on one line, entries split by semicolons, so it can
+    //be saved with the render settings. parse_inline reads it back
+    pub fn to_inline(&self) -> String {
+        let mode = match self.mode {
+            PaletteMode::Cyclic => "cyclic",
+            PaletteMode::Clamped => "clamp",
+        };
+        let [r, g, b] = self.inside;
+        let mut entries = vec![
+            format!("mode {}", mode),
+            format!("repeat {}", self.repeat),
+            format!("inside {} {} {}", r, g, b),
+        ];
+        for stop in &self.stops {
+            let [r, g, b] = stop.colour;
+            entries.push(format!("{} rgb {} {} {}", stop.position, r, g, b));
+        }
+        entries.join("; ")
+    }
+
+    pub fn parse_inline(text: &str) -> Result<Palette, String> {
+        Palette::parse(SAVED_PALETTE, &text.replace(';', "\n"))
+    }
+
+    //Colour at a position along the gradient, 0 and 1 being the ends
+    pub fn sample(&self, position: f64) -> [f64; 3] {
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        let t = match self.mode {
+            PaletteMode::Cyclic => position.rem_euclid(1.0),
+            PaletteMode::Clamped => position.clamp(0.0, 1.0),
+        };
+        //NaN, or infinity wrapped round, has nowhere on the gradient to go
+        if !t.is_finite() {
+            return first.colour;
+        }
+
+        if t < first.position || t >= last.position {
+            return match self.mode {
+                //Blend across the wrap from the last stop round to the first
+                PaletteMode::Cyclic => {
+                    let t = if t < first.position { t + 1.0 } else { t };
+                    let span = first.position + 1.0 - last.position;
+                    lerp(last.colour, first.colour, (t - last.position) / span)
+                }
+                PaletteMode::Clamped if t < first.position => first.colour,
+                PaletteMode::Clamped => last.colour,
+            };
+        }
+
+        let i = self
+            .stops
+            .iter()
+            .rposition(|s| s.position <= t)
+            .unwrap_or(0);
+        let (from, to) = (self.stops[i], self.stops[i + 1]);
+        lerp(
+            from.colour,
+            to.colour,
+            (t - from.position) / (to.position - from.position),
+        )
+    }
+
+    //Packed colour for an iteration value, same layout as iterations2colour
+    pub fn colour(&self, value: f64, max_iter: u32, offset: f64) -> u32 {
+        let colour = if value <= 0.0 {
+            self.inside
+        } else {
+            self.sample(value / max_iter as f64 * self.repeat + offset)
+        };
+        pack(colour)
+    }
+}
+
+fn lerp(from: [f64; 3], to: [f64; 3], t: f64) -> [f64; 3] {
+    let t = if t.is_finite() { t } else { 0.0 };
+    [
+        from[0] + (to[0] - from[0]) * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+    ]
+}
+
+pub fn pack(colour: [f64; 3]) -> u32 {
+    let channel = |c: f64| c.round().clamp(0.0, 255.0) as u32;
+    (channel(colour[2]) << 16) | (channel(colour[1]) << 8) | channel(colour[0])
+}
+
+//A built in palette name or the path of a palette file
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        match Palette::named(s) {
+            Some(palette) => Ok(palette),
+            None => Palette::load(s),
+        }
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_survives_non_finite_positions() {
+        let stops = vec![
+            Stop {
+                position: 0.25,
+                colour: rgb(10.0, 20.0, 30.0),
+            },
+            Stop {
+                position: 0.75,
+                colour: rgb(200.0, 100.0, 0.0),
+            },
+        ];
+        for mode in [PaletteMode::Cyclic, PaletteMode::Clamped] {
+            let palette = Palette::new("test", mode, stops.clone());
+            assert_eq!(palette.sample(f64::NAN), stops[0].colour);
+            assert_eq!(palette.sample(0.5), rgb(105.0, 60.0, 15.0));
+            palette.sample(f64::INFINITY);
+            palette.sample(f64::NEG_INFINITY);
+        }
+        let ultra = Palette::named("ultra").unwrap();
+        assert_eq!(ultra.sample(f64::NAN), ultra.stops[0].colour);
+        ultra.colour(f64::NAN, 256, 0.0);
+        ultra.colour(10.0, 256, f64::NAN);
+    }
+}
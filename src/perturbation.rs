@@ -0,0 +1,302 @@
+use crate::precision::{BigFixed, DoubleDouble, Precision, Real};
+use crate::{render_tiles, Derivative, Options, Sample, Tile, TileScheduler, Trackers, Trap};
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+//A pixel is glitched once |z| drops this far below |Z| (squared, so 1e-3 on the magnitudes)
+const GLITCH_TOLERANCE: f64 = 1e-6;
+//Largest allowed ratio between the cubic and linear series terms before we stop skipping
+const SERIES_TOLERANCE: f64 = 1e-12;
+//How many of the most recent rebased references a glitched pixel tries before making its own
+const MAX_REBASE_TRIES: usize = 8;
+
+type Complex = (f64, f64);
+
+#[inline]
+fn add(a: Complex, b: Complex) -> Complex {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+#[inline]
+fn mul(a: Complex, b: Complex) -> Complex {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+#[inline]
+fn norm(a: Complex) -> f64 {
+    a.0 * a.0 + a.1 * a.1
+}
+
+//Orbit of a single point iterated in full precision. Every other sample is iterated as a
+//small f64 delta from it, which is only valid while deltas stay above the f64 exponent range
+//(around 1e-300)
+#[derive(Clone, Debug)]
+pub struct ReferenceOrbit {
+    //Offset of the reference point from the centre of the image
+    pub offsetx: f64,
+    pub offsety: f64,
+    //Z_0 to Z_n, ending at the first point that escaped or once max_iter is reached
+    orbit: Vec<Complex>,
+    //Julia orbits start at the reference point and samples differ in z_0 rather than c
+    julia: bool,
+    //Square of the escape radius
+    bailout: f64,
+    //Carry the derivative along for the distance estimate
+    derivative: bool,
+    //Trap every sample's orbit is followed for
+    trap: Option<Trap>,
+    //Iterations skipped by the series approximation and its coefficients at that point
+    skip: usize,
+    series: [Complex; 3],
+}
+
+impl ReferenceOrbit {
+    //Reference at the centre of the image, with the series approximation if it is enabled
+    pub fn new(options: &Options) -> ReferenceOrbit {
+        let mut reference = ReferenceOrbit::at_offset(options, 0.0, 0.0);
+        //Traps need to see the iterations the series would skip
+        if options.series_approximation && options.active_trap().is_none() {
+            reference.approximate_series(options);
+        }
+        reference
+    }
+
+    pub fn at_offset(options: &Options, offsetx: f64, offsety: f64) -> ReferenceOrbit {
+        let (precision, bits) = options.precision.resolve(options.sample_size());
+        let cx = options.centrex.clone() + BigFixed::from_f64(offsetx, bits);
+        let cy = options.centrey.clone() + BigFixed::from_f64(offsety, bits);
+        let bailout = options.escape_radius * options.escape_radius;
+        let (z, c) = match &options.julia {
+            Some((re, im)) => ((cx, cy), (re.clone(), im.clone())),
+            None => ((BigFixed::zero(bits), BigFixed::zero(bits)), (cx, cy)),
+        };
+        //Mandelbrot orbits have the extra z_0 = 0 step before the first escape check
+        let steps = match options.julia {
+            Some(_) => options.max_iter,
+            None => options.max_iter + 1,
+        };
+        let orbit = match precision {
+            //f32 is never enough for a reference, deltas need the full orbit to be accurate
+            Precision::Single | Precision::Double => {
+                compute_orbit::<f64>(&z, &c, bits, steps, bailout)
+            }
+            Precision::DoubleDouble => compute_orbit::<DoubleDouble>(&z, &c, bits, steps, bailout),
+            Precision::Arbitrary | Precision::Auto => {
+                compute_orbit::<BigFixed>(&z, &c, bits, steps, bailout)
+            }
+        };
+        ReferenceOrbit {
+            offsetx,
+            offsety,
+            orbit,
+            julia: options.julia.is_some(),
+            bailout,
+            derivative: options.uses_derivative(),
+            trap: options.active_trap().cloned(),
+            skip: 0,
+            series: [(0.0, 0.0); 3],
+        }
+    }
+
+    //Iterations the reference managed before escaping
+    pub fn len(&self) -> usize {
+        self.orbit.len() - 1 - self.first_check()
+    }
+
+    //Index of the first orbit point that gets checked for escape, matching escape_time
+    fn first_check(&self) -> usize {
+        if self.julia {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //Iterations every sample skips thanks to the series approximation
+    pub fn skipped(&self) -> usize {
+        self.skip
+    }
+
+    //Run the cubic series delta_n = A d + B d^2 + C d^3 along the orbit and keep the last
+    //step where it is still accurate for the corner of the image furthest from the reference.
+    //d is dc for the Mandelbrot set and the starting delta_0 for Julia sets
+    fn approximate_series(&mut self, options: &Options) {
+        let scalex = options.scaley * options.width as f64 / options.height as f64;
+        let radius = (scalex * scalex + options.scaley * options.scaley).sqrt() * 0.5;
+
+        let (mut a, dc): (Complex, Complex) = if self.julia {
+            ((1.0, 0.0), (0.0, 0.0))
+        } else {
+            ((0.0, 0.0), (1.0, 0.0))
+        };
+        let mut b: Complex = (0.0, 0.0);
+        let mut c: Complex = (0.0, 0.0);
+        for n in 0..self.orbit.len() - 1 {
+            let z2 = (2.0 * self.orbit[n].0, 2.0 * self.orbit[n].1);
+            let next_a = add(mul(z2, a), dc);
+            let next_b = add(mul(z2, b), mul(a, a));
+            let ab = mul(a, b);
+            let next_c = add(mul(z2, c), (2.0 * ab.0, 2.0 * ab.1));
+
+            let linear = norm(next_a).sqrt() * radius;
+            let cubic = norm(next_c).sqrt() * radius * radius * radius;
+            let bound = linear + norm(next_b).sqrt() * radius * radius + cubic;
+            //Stop before the error term grows or any sample could escape inside the skip
+            if cubic > SERIES_TOLERANCE * linear
+                || norm(self.orbit[n + 1]).sqrt() + bound >= options.escape_radius
+            {
+                break;
+            }
+            a = next_a;
+            b = next_b;
+            c = next_c;
+            self.skip = n + 1;
+        }
+        self.series = [a, b, c];
+    }
+
+    //Iterate a sample d away from the reference. Returns None if the sample glitched or needs
+    //more iterations than the reference has, in which case it needs a new reference
+    pub fn iterate(&self, d: Complex, max_iter: u32) -> Option<Sample> {
+        //dz is the derivative by c, or by z_0 for Julia sets, where the series gives it as
+        //A + 2 B d + 3 C d^2
+        let (mut delta, dc, dz) = if self.skip > 0 {
+            let [a, b, c] = self.series;
+            let d2 = mul(d, d);
+            let delta = add(add(mul(a, d), mul(b, d2)), mul(c, mul(d2, d)));
+            let dz = add(
+                add(a, mul((2.0 * b.0, 2.0 * b.1), d)),
+                mul((3.0 * c.0, 3.0 * c.1), d2),
+            );
+            (delta, if self.julia { (0.0, 0.0) } else { d }, dz)
+        } else if self.julia {
+            (d, (0.0, 0.0), (1.0, 0.0))
+        } else {
+            ((0.0, 0.0), d, (0.0, 0.0))
+        };
+        let mut trackers = Trackers {
+            trap: self.trap.as_ref().map(Trap::tracker),
+            derivative: if self.derivative {
+                Some(Derivative {
+                    dz,
+                    ..Derivative::new(self.julia)
+                })
+            } else {
+                None
+            },
+        };
+        let mut n = self.skip;
+
+        //delta_n+1 = 2 Z_n delta_n + delta_n^2 + dc
+        let step = |n: usize, delta: Complex| {
+            let z = self.orbit[n];
+            let twice = mul((2.0 * z.0, 2.0 * z.1), delta);
+            add(add(twice, mul(delta, delta)), dc)
+        };
+        while n < self.first_check() {
+            if let Some(derivative) = trackers.derivative.as_mut() {
+                let z = add(self.orbit[n], delta);
+                derivative.step(z.0, z.1);
+            }
+            delta = step(n, delta);
+            n += 1;
+        }
+
+        //Same counting as escape_time
+        let mut iter = (n - self.first_check()) as u32;
+        let mut full = 0.0;
+        while iter <= max_iter {
+            let z = add(self.orbit[n], delta);
+            full = norm(z);
+            if full >= self.bailout {
+                if let Some(derivative) = trackers.derivative.as_mut() {
+                    derivative.escaped = Some(z);
+                }
+                return Some(Sample::new(iter, full).with_trackers(trackers));
+            }
+            if full < GLITCH_TOLERANCE * norm(self.orbit[n]) {
+                return None;
+            }
+            trackers.visit(&z);
+            iter += 1;
+            if iter > max_iter {
+                break;
+            }
+            if n + 1 >= self.orbit.len() {
+                return None;
+            }
+            delta = step(n, delta);
+            n += 1;
+        }
+        Some(Sample::new(max_iter + 1, full).with_trackers(trackers))
+    }
+}
+
+fn compute_orbit<R: Real>(
+    z: &(BigFixed, BigFixed),
+    c: &(BigFixed, BigFixed),
+    bits: u32,
+    steps: u32,
+    bailout: f64,
+) -> Vec<Complex> {
+    let cx = R::from_fixed(&c.0, bits);
+    let cy = R::from_fixed(&c.1, bits);
+    let mut x = R::from_fixed(&z.0, bits);
+    let mut y = R::from_fixed(&z.1, bits);
+    let mut orbit = vec![(x.to_f64(), y.to_f64())];
+    for _ in 0..steps {
+        if norm(orbit[orbit.len() - 1]) >= bailout {
+            break;
+        }
+        let x2 = x.clone() * x.clone();
+        let y2 = y.clone() * y.clone();
+        let xy = x * y;
+        x = x2 - y2 + cx.clone();
+        y = xy.clone() + xy + cy.clone();
+        orbit.push((x.to_f64(), y.to_f64()));
+    }
+    orbit
+}
+
+//Perturbation worker. Works through tiles like mandelbrot but iterates every sample as a delta
+//from the shared reference, rebasing glitched samples onto references made at their own position
+pub fn perturbation(
+    options: Options,
+    reference: Arc<ReferenceOrbit>,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+) {
+    //Only the newest few are ever tried again, so older ones are let go
+    let mut rebased: VecDeque<ReferenceOrbit> = VecDeque::with_capacity(MAX_REBASE_TRIES);
+
+    render_tiles(&options, sender, scheduler, |offsetx, offsety, _| {
+        let dc = (offsetx - reference.offsetx, offsety - reference.offsety);
+        if let Some(iter) = reference.iterate(dc, options.max_iter) {
+            return iter;
+        }
+
+        //Glitches come in blobs so a reference made for a neighbour usually fixes this one too
+        for other in rebased.iter().rev() {
+            let dc = (offsetx - other.offsetx, offsety - other.offsety);
+            if let Some(iter) = other.iterate(dc, options.max_iter) {
+                return iter;
+            }
+        }
+
+        //A reference at the sample itself has a zero delta, so it can't glitch
+        let own = ReferenceOrbit::at_offset(&options, offsetx, offsety);
+        let result = own
+            .iterate((0.0, 0.0), options.max_iter)
+            .unwrap_or(Sample::new(own.len() as u32, own.bailout));
+        if rebased.len() == MAX_REBASE_TRIES {
+            rebased.pop_front();
+        }
+        rebased.push_back(own);
+        result
+    });
+}
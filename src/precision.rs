@@ -0,0 +1,742 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+//Extra bits kept on top of what the pixel spacing needs so rounding doesn't show up as blocks
+const GUARD_BITS: u32 = 12;
+
+//Most bits resolve hands out. Pixels as small as the smallest f64, 2^-1074, need no more and
+//anything smaller, such as a zero pixel size, can't be resolved anyway
+const MAX_BITS: u32 = 1074 + GUARD_BITS;
+
+//Most digits after the point a parsed number keeps. The finest pixels resolve can be asked for
+//are the smallest f64, 2^-1074, and 400 digits is over 1300 bits so nothing it needs is lost
+const MAX_FRACTION_DIGITS: usize = 400;
+
+//Arithmetic backend used for the coordinate math in the worker
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Precision {
+    Auto,
+    Single,
+    Double,
+    DoubleDouble,
+    Arbitrary,
+}
+
+impl Precision {
+    //Pick the cheapest backend that can resolve pixels of the given size.
+    //Returns the concrete precision along with the number of bits the worker should carry
+    pub fn resolve(self, pixel_size: f64) -> (Precision, u32) {
+        let bits = ((-pixel_size.log2()).ceil().max(0.0) as u32)
+            .saturating_add(GUARD_BITS)
+            .min(MAX_BITS);
+        let precision = match self {
+            Precision::Auto => {
+                if bits <= 24 {
+                    Precision::Single
+                } else if bits <= 53 {
+                    Precision::Double
+                } else if bits <= 106 {
+                    Precision::DoubleDouble
+                } else {
+                    Precision::Arbitrary
+                }
+            }
+            precision => precision,
+        };
+        (precision, bits)
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Precision, String> {
+        match s {
+            "auto" => Ok(Precision::Auto),
+            "single" => Ok(Precision::Single),
+            "double" => Ok(Precision::Double),
+            "double-double" => Ok(Precision::DoubleDouble),
+            "arbitrary" => Ok(Precision::Arbitrary),
+            _ => Err(format!("Unknown precision {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Precision::Auto => "auto",
+            Precision::Single => "single",
+            Precision::Double => "double",
+            Precision::DoubleDouble => "double-double",
+            Precision::Arbitrary => "arbitrary",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//Number types the worker can iterate with. bits is only used by types that can vary their precision
+pub trait Real:
+    Clone
+    + PartialEq
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Neg<Output = Self>
+{
+    fn from_fixed(value: &BigFixed, bits: u32) -> Self;
+    fn from_f64(value: f64, bits: u32) -> Self;
+    fn to_f64(&self) -> f64;
+    fn abs(self) -> Self;
+}
+
+impl Real for f32 {
+    fn from_fixed(value: &BigFixed, _bits: u32) -> f32 {
+        value.to_f64() as f32
+    }
+
+    fn from_f64(value: f64, _bits: u32) -> f32 {
+        value as f32
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+
+    fn abs(self) -> f32 {
+        f32::abs(self)
+    }
+}
+
+impl Real for f64 {
+    fn from_fixed(value: &BigFixed, _bits: u32) -> f64 {
+        value.to_f64()
+    }
+
+    fn from_f64(value: f64, _bits: u32) -> f64 {
+        value
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn abs(self) -> f64 {
+        f64::abs(self)
+    }
+}
+
+//Unevaluated sum of two doubles giving roughly 106 bits of mantissa
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DoubleDouble {
+    pub hi: f64,
+    pub lo: f64,
+}
+
+impl DoubleDouble {
+    pub fn new(hi: f64, lo: f64) -> DoubleDouble {
+        DoubleDouble { hi, lo }
+    }
+}
+
+#[inline]
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+#[inline]
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+//Dekker split so the exact product doesn't depend on having a hardware fma
+#[inline]
+fn split(a: f64) -> (f64, f64) {
+    let t = 134217729.0 * a;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+#[inline]
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    let (ahi, alo) = split(a);
+    let (bhi, blo) = split(b);
+    (p, ((ahi * bhi - p) + ahi * blo + alo * bhi) + alo * blo)
+}
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn add(self, other: DoubleDouble) -> DoubleDouble {
+        let (s, e) = two_sum(self.hi, other.hi);
+        let (t, f) = two_sum(self.lo, other.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        let (hi, lo) = quick_two_sum(s, e + f);
+        DoubleDouble { hi, lo }
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn sub(self, other: DoubleDouble) -> DoubleDouble {
+        self + -other
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn mul(self, other: DoubleDouble) -> DoubleDouble {
+        let (p, e) = two_prod(self.hi, other.hi);
+        let (hi, lo) = quick_two_sum(p, e + (self.hi * other.lo + self.lo * other.hi));
+        DoubleDouble { hi, lo }
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn neg(self) -> DoubleDouble {
+        DoubleDouble {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+impl Real for DoubleDouble {
+    fn from_fixed(value: &BigFixed, bits: u32) -> DoubleDouble {
+        let hi = value.to_f64();
+        let lo = (value.clone() - BigFixed::from_f64(hi, bits)).to_f64();
+        DoubleDouble { hi, lo }
+    }
+
+    fn from_f64(value: f64, _bits: u32) -> DoubleDouble {
+        DoubleDouble { hi: value, lo: 0.0 }
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.hi + self.lo
+    }
+
+    fn abs(self) -> DoubleDouble {
+        if self.hi < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+//Sign-magnitude fixed point number. limbs[0] is the integer part and every following
+//limb holds another 32 bits of fraction, so precision is only limited by memory. Results too
+//big for the integer limb saturate just below BigFixed::LIMIT rather than wrapping
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigFixed {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+fn limbs_for_bits(bits: u32) -> usize {
+    bits.div_ceil(32).max(1) as usize
+}
+
+impl BigFixed {
+    //Magnitudes have to stay below this. An orbit that saturates has |z|^2 close to it, so it
+    //only counts as escaped when the bailout is below it as well
+    pub const LIMIT: f64 = 4294967296.0;
+
+    pub fn zero(bits: u32) -> BigFixed {
+        BigFixed {
+            negative: false,
+            limbs: vec![0; limbs_for_bits(bits) + 1],
+        }
+    }
+
+    pub fn from_f64(value: f64, bits: u32) -> BigFixed {
+        let mut result = BigFixed::zero(bits);
+        let mut magnitude = value.abs();
+        result.negative = value < 0.0;
+        result.limbs[0] = magnitude.trunc() as u32;
+        magnitude = magnitude.fract();
+        //Each step moves the next 32 bits above the point, which is exact in floating point
+        for limb in result.limbs.iter_mut().skip(1) {
+            magnitude *= 4294967296.0;
+            *limb = magnitude.trunc() as u32;
+            magnitude = magnitude.fract();
+        }
+        result
+    }
+
+    //Number of fractional bits carried
+    pub fn bits(&self) -> u32 {
+        (self.limbs.len() as u32 - 1) * 32
+    }
+
+    pub fn with_bits(&self, bits: u32) -> BigFixed {
+        let mut limbs = self.limbs.clone();
+        limbs.resize(limbs_for_bits(bits) + 1, 0);
+        BigFixed {
+            negative: self.negative,
+            limbs,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.iter().all(|&limb| limb == 0)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mut result = 0.0;
+        let mut scale = 1.0;
+        for &limb in self.limbs.iter() {
+            if scale == 0.0 {
+                break;
+            }
+            result += limb as f64 * scale;
+            scale /= 4294967296.0;
+        }
+        if self.negative {
+            -result
+        } else {
+            result
+        }
+    }
+
+    fn padded(&self, len: usize) -> Vec<u32> {
+        let mut limbs = self.limbs.clone();
+        limbs.resize(len, 0);
+        limbs
+    }
+
+    fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut result = vec![0; a.len()];
+        let mut carry = 0u64;
+        for i in (0..a.len()).rev() {
+            let sum = a[i] as u64 + b[i] as u64 + carry;
+            result[i] = sum as u32;
+            carry = sum >> 32;
+        }
+        if carry != 0 {
+            return vec![u32::MAX; a.len()];
+        }
+        result
+    }
+
+    //a must not be smaller than b
+    fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut result = vec![0; a.len()];
+        let mut borrow = 0i64;
+        for i in (0..a.len()).rev() {
+            let mut diff = a[i] as i64 - b[i] as i64 - borrow;
+            borrow = 0;
+            if diff < 0 {
+                diff += 1 << 32;
+                borrow = 1;
+            }
+            result[i] = diff as u32;
+        }
+        result
+    }
+
+    fn add_signed(&self, other: &BigFixed, other_negative: bool) -> BigFixed {
+        let len = self.limbs.len().max(other.limbs.len());
+        let a = self.padded(len);
+        let b = other.padded(len);
+        if self.negative == other_negative {
+            return BigFixed {
+                negative: self.negative,
+                limbs: BigFixed::add_magnitudes(&a, &b),
+            };
+        }
+        match a.cmp(&b) {
+            Ordering::Less => BigFixed {
+                negative: other_negative,
+                limbs: BigFixed::sub_magnitudes(&b, &a),
+            },
+            _ => BigFixed {
+                negative: self.negative,
+                limbs: BigFixed::sub_magnitudes(&a, &b),
+            },
+        }
+    }
+
+    fn mul_ref(&self, other: &BigFixed) -> BigFixed {
+        //Schoolbook multiply on little endian copies then keep the limbs around the point
+        let a: Vec<u32> = self.limbs.iter().rev().cloned().collect();
+        let b: Vec<u32> = other.limbs.iter().rev().cloned().collect();
+        let mut product = vec![0u32; a.len() + b.len()];
+        for (i, &ai) in a.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &bj) in b.iter().enumerate() {
+                let cur = product[i + j] as u64 + ai as u64 * bj as u64 + carry;
+                product[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            product[i + b.len()] = carry as u32;
+        }
+
+        let frac = (a.len() - 1).max(b.len() - 1);
+        let drop = (a.len() - 1) + (b.len() - 1) - frac;
+        let limbs: Vec<u32> = if product[drop + frac + 1..].iter().any(|&limb| limb != 0) {
+            vec![u32::MAX; frac + 1]
+        } else {
+            product[drop..drop + frac + 1]
+                .iter()
+                .rev()
+                .cloned()
+                .collect()
+        };
+        BigFixed {
+            negative: self.negative != other.negative,
+            limbs,
+        }
+    }
+
+    fn mul_small(&mut self, factor: u32) {
+        let mut carry = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let cur = *limb as u64 * factor as u64 + carry;
+            *limb = cur as u32;
+            carry = cur >> 32;
+        }
+    }
+
+    fn div_small(&mut self, divisor: u32) {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut() {
+            let cur = (remainder << 32) | *limb as u64;
+            *limb = (cur / divisor as u64) as u32;
+            remainder = cur % divisor as u64;
+        }
+    }
+}
+
+impl Add for BigFixed {
+    type Output = BigFixed;
+
+    fn add(self, other: BigFixed) -> BigFixed {
+        self.add_signed(&other, other.negative)
+    }
+}
+
+impl Sub for BigFixed {
+    type Output = BigFixed;
+
+    fn sub(self, other: BigFixed) -> BigFixed {
+        self.add_signed(&other, !other.negative)
+    }
+}
+
+impl Mul for BigFixed {
+    type Output = BigFixed;
+
+    fn mul(self, other: BigFixed) -> BigFixed {
+        self.mul_ref(&other)
+    }
+}
+
+impl Neg for BigFixed {
+    type Output = BigFixed;
+
+    fn neg(mut self) -> BigFixed {
+        self.negative = !self.negative;
+        self
+    }
+}
+
+impl Real for BigFixed {
+    fn from_fixed(value: &BigFixed, bits: u32) -> BigFixed {
+        value.with_bits(bits)
+    }
+
+    fn from_f64(value: f64, bits: u32) -> BigFixed {
+        BigFixed::from_f64(value, bits)
+    }
+
+    fn to_f64(&self) -> f64 {
+        BigFixed::to_f64(self)
+    }
+
+    fn abs(mut self) -> BigFixed {
+        self.negative = false;
+        self
+    }
+}
+
+impl From<f64> for BigFixed {
+    //Enough bits to hold any f64 of ordinary magnitude exactly
+    fn from(value: f64) -> BigFixed {
+        BigFixed::from_f64(value, 64)
+    }
+}
+
+//Accepts plain decimals with an optional exponent e.g. -0.7436438870371587 or 1.5e-3
+impl FromStr for BigFixed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BigFixed, String> {
+        let bad = || format!("Invalid number {}", s);
+        let s = s.trim();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (mantissa, exponent) = match s.find(['e', 'E']) {
+            Some(pos) => (&s[..pos], s[pos + 1..].parse::<i64>().map_err(|_| bad())?),
+            None => (s, 0),
+        };
+        let (int_digits, frac_digits) = match mantissa.find('.') {
+            Some(pos) => (&mantissa[..pos], &mantissa[pos + 1..]),
+            None => (mantissa, ""),
+        };
+        let digits: Vec<u8> = int_digits.bytes().chain(frac_digits.bytes()).collect();
+        if digits.is_empty() || !digits.iter().all(|d| d.is_ascii_digit()) {
+            return Err(bad());
+        }
+
+        //Digits are numbered from the first one, point is where the exponent moves the decimal
+        //point to. Only MAX_FRACTION_DIGITS after it are read and anything further is dropped
+        let point = (int_digits.len() as i64).saturating_add(exponent);
+        let digit = |i: i64| {
+            if i >= 0 && (i as usize) < digits.len() {
+                (digits[i as usize] - b'0') as u32
+            } else {
+                0
+            }
+        };
+        let mut integer: u32 = 0;
+        if let Some(first) = digits.iter().position(|&d| d != b'0') {
+            //More than 10 digits can never fit
+            if point.saturating_sub(first as i64) > 10 {
+                return Err(bad());
+            }
+            for i in first as i64..point {
+                integer = integer
+                    .checked_mul(10)
+                    .and_then(|v| v.checked_add(digit(i)))
+                    .ok_or_else(bad)?;
+            }
+        }
+
+        let frac_len = (digits.len() as i64)
+            .saturating_sub(point)
+            .clamp(0, MAX_FRACTION_DIGITS as i64);
+        //log2(10) bits per digit plus one spare limb
+        let bits = (frac_len as f64 * std::f64::consts::LOG2_10).ceil() as u32 + 32;
+        let mut result = BigFixed::zero(bits.max(64));
+        for i in (point..point + frac_len).rev() {
+            result.limbs[0] = digit(i);
+            result.div_small(10);
+        }
+        result.limbs[0] = integer;
+        result.negative = negative && !result.is_zero();
+        Ok(result)
+    }
+}
+
+impl fmt::Display for BigFixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        //Only print the digits the fraction can actually resolve, rounded on the last one
+        let digits = (self.bits() as f64 * std::f64::consts::LOG10_2).floor() as usize;
+        let mut frac = self.clone();
+        frac.limbs[0] = 0;
+        let mut out: Vec<u8> = Vec::with_capacity(digits + 1);
+        for _ in 0..digits + 1 {
+            if frac.is_zero() {
+                break;
+            }
+            frac.mul_small(10);
+            out.push(frac.limbs[0] as u8);
+            frac.limbs[0] = 0;
+        }
+
+        let mut integer = self.limbs[0] as u64;
+        if out.len() > digits {
+            let last = out.pop().unwrap();
+            if last >= 5 {
+                let mut i = out.len();
+                loop {
+                    if i == 0 {
+                        integer += 1;
+                        break;
+                    }
+                    i -= 1;
+                    if out[i] == 9 {
+                        out[i] = 0;
+                    } else {
+                        out[i] += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        while out.last() == Some(&0) {
+            out.pop();
+        }
+
+        if self.negative && (integer != 0 || !out.is_empty()) {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", integer)?;
+        if !out.is_empty() {
+            let text: String = out.iter().map(|d| (b'0' + d) as char).collect();
+            write!(f, ".{}", text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(s: &str) -> BigFixed {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn resolve_caps_the_bits() {
+        assert_eq!(
+            Precision::Auto.resolve(1.0),
+            (Precision::Single, GUARD_BITS)
+        );
+        assert_eq!(Precision::Auto.resolve(1e-10).0, Precision::Double);
+        for size in [0.0, -0.0, 5e-324] {
+            assert_eq!(
+                Precision::Auto.resolve(size),
+                (Precision::Arbitrary, MAX_BITS),
+                "{}",
+                size
+            );
+        }
+        assert_eq!(
+            Precision::Double.resolve(0.0),
+            (Precision::Double, MAX_BITS)
+        );
+    }
+
+    #[test]
+    fn fixed_arithmetic_matches_f64() {
+        let values = [0.0, 0.25, -0.75, 1.5, -2.125, 3.0517578125e-5, -1234.5];
+        for &a in &values {
+            for &b in &values {
+                let (x, y) = (BigFixed::from(a), BigFixed::from(b));
+                assert_eq!((x.clone() + y.clone()).to_f64(), a + b, "{} + {}", a, b);
+                assert_eq!((x.clone() - y.clone()).to_f64(), a - b, "{} - {}", a, b);
+                let product = (x * y).to_f64();
+                assert!(
+                    (product - a * b).abs() <= (a * b).abs() * 1e-15,
+                    "{} * {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_signs() {
+        assert_eq!((fixed("0.5") - fixed("2")).to_string(), "-1.5");
+        assert_eq!((fixed("-0.5") - fixed("-2")).to_string(), "1.5");
+        assert_eq!((fixed("-3") * fixed("-0.5")).to_string(), "1.5");
+        assert_eq!((fixed("-3") * fixed("0.5")).to_string(), "-1.5");
+        assert_eq!((-fixed("0.25")).to_string(), "-0.25");
+        assert_eq!(fixed("-0").to_string(), "0");
+        assert_eq!((fixed("1.25") - fixed("1.25")).to_string(), "0");
+    }
+
+    #[test]
+    fn fixed_carries_into_the_integer() {
+        let almost = BigFixed {
+            negative: false,
+            limbs: vec![0, u32::MAX, u32::MAX],
+        };
+        let tiny = BigFixed {
+            negative: false,
+            limbs: vec![0, 0, 1],
+        };
+        assert_eq!((almost + tiny).limbs, vec![1, 0, 0]);
+        assert_eq!((fixed("65535") * fixed("65535")).to_f64(), 4294836225.0);
+    }
+
+    #[test]
+    fn fixed_saturates_on_overflow() {
+        let square = fixed("70000") * fixed("70000");
+        assert!(square.to_f64() >= BigFixed::LIMIT - 1.0);
+        let sum = fixed("4294967295") + fixed("1");
+        assert!(sum.to_f64() >= BigFixed::LIMIT - 1.0);
+        let negative = fixed("-70000") * fixed("70000");
+        assert!(negative.to_f64() <= -(BigFixed::LIMIT - 1.0));
+    }
+
+    #[test]
+    fn fixed_parses_and_displays_decimals() {
+        assert_eq!(
+            fixed("-0.7436438870371587").to_string(),
+            "-0.7436438870371587"
+        );
+        assert_eq!(fixed("1.5e-3").to_string(), "0.0015");
+        assert_eq!(fixed("+2").to_string(), "2");
+        assert_eq!(fixed("25E-1").to_string(), "2.5");
+        assert_eq!(fixed("0.000125e3").to_string(), "0.125");
+        assert_eq!(fixed("4294967295").to_string(), "4294967295");
+        assert_eq!(fixed("0.1").to_f64(), 0.1);
+        let long = "0.123456789012345678901234567890123456789";
+        assert_eq!(fixed(long).to_string(), long);
+    }
+
+    #[test]
+    fn fixed_rejects_bad_numbers() {
+        for bad in ["", "-", "abc", "1e", "1.2.3", "0x10", "4294967296", "1e10"] {
+            assert!(bad.parse::<BigFixed>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn fixed_parses_extreme_exponents_as_zero() {
+        assert!(fixed("1e-300000").is_zero());
+        assert!(fixed("0e99999999999").is_zero());
+        assert_eq!(fixed("-5e-450").to_string(), "0");
+        assert!(!fixed("5e-350").is_zero());
+        assert!("1e99999999999".parse::<BigFixed>().is_err());
+    }
+
+    #[test]
+    fn double_double_arithmetic_matches_f64() {
+        let values = [0.0, 0.25, -0.75, 1.5, -2.125, 3.0e-5];
+        for &a in &values {
+            for &b in &values {
+                let (x, y) = (DoubleDouble::new(a, 0.0), DoubleDouble::new(b, 0.0));
+                assert_eq!((x + y).to_f64(), a + b);
+                assert_eq!((x - y).to_f64(), a - b);
+                assert_eq!((x * y).to_f64(), a * b);
+            }
+        }
+        assert_eq!((-DoubleDouble::new(-1.0, -1e-20)).hi, 1.0);
+        assert_eq!(DoubleDouble::new(-1.0, 0.0).abs().hi, 1.0);
+    }
+
+    #[test]
+    fn double_double_keeps_bits_past_f64() {
+        let tiny = 2f64.powi(-60);
+        let sum = DoubleDouble::new(1.0, 0.0) + DoubleDouble::new(tiny, 0.0);
+        assert_eq!((sum - DoubleDouble::new(1.0, 0.0)).to_f64(), tiny);
+
+        let x = DoubleDouble::new(1.0 + 2f64.powi(-30), 0.0);
+        let square = x * x - DoubleDouble::new(1.0 + 2f64.powi(-29), 0.0);
+        assert_eq!(square.to_f64(), tiny);
+
+        let tenth =
+            DoubleDouble::from_fixed(&fixed("0.10000000000000000000000000000000000000"), 128);
+        assert_eq!(tenth.hi, 0.1);
+        assert!((tenth.lo + 5.551115123125783e-18).abs() < 1e-30);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{BigFixed, Error, Options, Renderer};
+use std::fs;
+use std::path::Path;
+
+pub const TILE_SIZE: u32 = 256;
+//Most levels a pyramid can have, so the tile count of the deepest level and its size in
+//pixels both fit a u32
+pub const MAX_LEVELS: u32 = 16;
+
+//Zoomable tile pyramid of the square around the view centre that is options.scaley across.
+//Level 0 is one tile showing all of it and each level after splits every tile into four, so
+//level z is 2^z tiles wide. Every tile is rendered at its own centre and scale, nothing is
+//scaled down from a bigger image
+#[derive(Clone, Debug)]
+pub struct Pyramid {
+    pub options: Options,
+    pub levels: u32,
+}
+
+impl Pyramid {
+    //The options get checked when the renderer is made from them
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.levels == 0 || self.levels > MAX_LEVELS {
+            Err(Error::Options(format!(
+                "Pyramid levels {} must be from 1 to {}",
+                self.levels, MAX_LEVELS
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    //Width and height in pixels of the deepest level put together
+    pub fn size(&self) -> u32 {
+        TILE_SIZE << (self.levels - 1)
+    }
+
+    //Options for rendering the part of the square at column x and row y of the grid of
+    //2^zoom by 2^zoom parts, into an image size pixels across
+    fn part_options(&self, zoom: u32, x: u32, y: u32, size: u32) -> Options {
+        let across = (1u64 << zoom) as f64;
+        let side = self.options.scaley;
+        let scale = side / across;
+
+        //Offset of the part's centre from the view centre, turned with the view
+        let u = ((x as f64 + 0.5) / across - 0.5) * side;
+        let v = ((y as f64 + 0.5) / across - 0.5) * side;
+        let (sin, cos) = self.options.rotation.to_radians().sin_cos();
+        let (u, v) = (u * cos - v * sin, u * sin + v * cos);
+        let bits = 64 + (1.0 / scale).log2().max(0.0).ceil() as u32;
+
+        let mut options = self.options.clone();
+        options.width = size;
+        options.height = size;
+        options.scaley = scale;
+        options.centrex = self.options.centrex.clone() + BigFixed::from_f64(u, bits);
+        options.centrey = self.options.centrey.clone() + BigFixed::from_f64(v, bits);
+        options
+    }
+
+    pub fn tile_options(&self, zoom: u32, x: u32, y: u32) -> Options {
+        self.part_options(zoom, x, y, TILE_SIZE)
+    }
+
+    //Deep Zoom manifest for the pyramid, its tiles go in a directory next to it named after
+    //the manifest with _files on the end
+    pub fn dzi_manifest(&self) -> String {
+        format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" ",
+                "Overlap=\"0\" TileSize=\"{}\">\n",
+                "  <Size Width=\"{}\" Height=\"{}\"/>\n",
+                "</Image>\n"
+            ),
+            TILE_SIZE,
+            self.size(),
+            self.size()
+        )
+    }
+
+    //Renders every tile into dir as z/x/y.png, then writes pyramid.dzi with its tiles in
+    //pyramid_files. Deep Zoom levels go all the way down to a single pixel, the levels smaller
+    //than a tile are rendered at their own size and the rest share the z/x/y tiles.
+    //on_level is called after each level with its number and tile count
+    pub fn export<F: FnMut(u32, u32)>(&self, dir: &str, mut on_level: F) -> Result<(), Error> {
+        let error = |path: &Path, e: std::io::Error| Error::io(&path.to_string_lossy(), e);
+        let dir = Path::new(dir);
+        let dzi_dir = dir.join("pyramid_files");
+        self.validate()?;
+        let mut renderer = Renderer::new(self.options.clone())?;
+
+        for zoom in 0..self.levels {
+            let across = 1u32 << zoom;
+            for x in 0..across {
+                let column = dir.join(zoom.to_string()).join(x.to_string());
+                fs::create_dir_all(&column).map_err(|e| error(&column, e))?;
+                for y in 0..across {
+                    renderer.set_options(self.tile_options(zoom, x, y))?;
+                    let path = column.join(format!("{}.png", y));
+                    renderer
+                        .render()?
+                        .image
+                        .save(&path)
+                        .map_err(|e| Error::image(&path.to_string_lossy(), e))?;
+                }
+            }
+            on_level(zoom, across * across);
+        }
+
+        //A 256 pixel image is Deep Zoom level 8, z/x/y level 0
+        let tile_level = TILE_SIZE.trailing_zeros();
+        for level in 0..tile_level + self.levels {
+            let level_dir = dzi_dir.join(level.to_string());
+            fs::create_dir_all(&level_dir).map_err(|e| error(&level_dir, e))?;
+            if level < tile_level {
+                renderer.set_options(self.part_options(0, 0, 0, 1 << level))?;
+                let path = level_dir.join("0_0.png");
+                renderer
+                    .render()?
+                    .image
+                    .save(&path)
+                    .map_err(|e| Error::image(&path.to_string_lossy(), e))?;
+                continue;
+            }
+            let zoom = level - tile_level;
+            for x in 0..1u32 << zoom {
+                for y in 0..1u32 << zoom {
+                    let tile = dir
+                        .join(zoom.to_string())
+                        .join(x.to_string())
+                        .join(format!("{}.png", y));
+                    let link = level_dir.join(format!("{}_{}.png", x, y));
+                    let _ = fs::remove_file(&link);
+                    //Copy where the file system can't link
+                    fs::hard_link(&tile, &link)
+                        .or_else(|_| fs::copy(&tile, &link).map(|_| ()))
+                        .map_err(|e| error(&link, e))?;
+                }
+            }
+        }
+
+        let manifest = dir.join("pyramid.dzi");
+        fs::write(&manifest, self.dzi_manifest()).map_err(|e| error(&manifest, e))
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{
+    mandelbrot, perturbation, Error, FormulaType, Options, Pixel, PixelExtras, ReferenceOrbit,
+    RenderStats, Tile, TileScheduler,
+};
+use image::{ImageBuffer, RgbImage};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+//Fixed set of worker threads that stay alive between renders. Jobs go into one shared channel
+//and whichever worker is free picks up the next one
+struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    fn new(threads: u32) -> ThreadPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    //The lock is only held while waiting for a job, not while running it
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => break,
+                    };
+                    match job {
+                        //A job that panics only loses its own tiles, which the render notices
+                        //never arrived. The worker carries on with the next job
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        ThreadPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    fn len(&self) -> u32 {
+        self.workers.len() as u32
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, job: F) -> Result<(), Error> {
+        let stopped = || Error::Render(String::from("The worker threads have stopped"));
+        self.sender
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send(Box::new(job))
+            .map_err(|_| stopped())
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        //Closing the channel makes every worker leave its loop
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+//Everything a render produced. pixels holds the per-pixel values in row order, with extras
+//alongside when the options use them, image is the finished colour image
+pub struct RenderResult {
+    pub pixels: Vec<Pixel>,
+    pub extras: Vec<PixelExtras>,
+    pub image: RgbImage,
+    pub stats: RenderStats,
+    //Length of the reference orbit and how many iterations series approximation skipped,
+    //when perturbation was used
+    pub reference: Option<(usize, usize)>,
+}
+
+//Renders images on a pool of options.threads worker threads. The pool is kept for the life of
+//the renderer so rendering many images, such as animation frames, only starts threads once
+pub struct Renderer {
+    options: Options,
+    pool: ThreadPool,
+}
+
+impl Renderer {
+    pub fn new(options: Options) -> Result<Renderer, Error> {
+        options.validate()?;
+        let pool = ThreadPool::new(options.threads);
+        Ok(Renderer { options, pool })
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    //Options for the next render. The pool only gets rebuilt if the thread count changed
+    pub fn set_options(&mut self, options: Options) -> Result<(), Error> {
+        options.validate()?;
+        if options.threads != self.pool.len() {
+            self.pool = ThreadPool::new(options.threads);
+        }
+        self.options = options;
+        Ok(())
+    }
+
+    //Number of tiles the image is split into, handy for sizing a progress bar
+    pub fn tile_count(&self) -> u32 {
+        TileScheduler::new(
+            self.options.width,
+            self.options.height,
+            self.options.tile_size,
+        )
+        .len()
+    }
+
+    pub fn render(&self) -> Result<RenderResult, Error> {
+        self.render_with(|_| {})
+    }
+
+    //Same as render but calls on_tile for each tile as it arrives from the workers
+    pub fn render_with<F: FnMut(&Tile)>(&self, on_tile: F) -> Result<RenderResult, Error> {
+        self.resume_with(&[], on_tile)
+    }
+
+    //Finishes a render that already has the tiles in done, only the missing tiles get rendered
+    //and passed to on_tile. The done tiles must come from a render with the same options
+    pub fn resume_with<F: FnMut(&Tile)>(
+        &self,
+        done: &[Tile],
+        mut on_tile: F,
+    ) -> Result<RenderResult, Error> {
+        let options = &self.options;
+        let indices: Vec<u32> = done.iter().map(|tile| tile.rect.index).collect();
+        let scheduler =
+            TileScheduler::with_done(options.width, options.height, options.tile_size, &indices);
+        let reference = self.reference_orbit();
+        let (rx, expected) = self.start(scheduler, &reference)?;
+
+        let count = options.width as usize * options.height as usize;
+        let mut pixels = vec![Pixel::default(); count];
+        let mut extras = if options.uses_extras() {
+            vec![PixelExtras::default(); count]
+        } else {
+            Vec::new()
+        };
+        let mut stats = RenderStats::default();
+        for tile in done {
+            tile.copy_into(&mut pixels, &mut extras, options.width);
+            stats.add(&tile.stats);
+        }
+        let mut received = 0;
+        for tile in rx {
+            tile.copy_into(&mut pixels, &mut extras, options.width);
+            stats.add(&tile.stats);
+            on_tile(&tile);
+            received += 1;
+        }
+        missing_tiles(received, expected)?;
+
+        Ok(RenderResult {
+            image: to_image(&pixels, options.width, options.height),
+            pixels,
+            extras,
+            stats,
+            reference: reference.map(|reference| (reference.len(), reference.skipped())),
+        })
+    }
+
+    //Renders the image a band of rows at a time from the top, so memory use depends on the
+    //width and band height but not the image height. on_band gets the pixels of each band in
+    //row order and can stop the render by returning an error
+    pub fn render_bands<F: FnMut(&[Pixel]) -> Result<(), Error>>(
+        &self,
+        band_height: u32,
+        mut on_band: F,
+    ) -> Result<RenderStats, Error> {
+        let options = &self.options;
+        let tiles = TileScheduler::new(options.width, options.height, options.tile_size);
+        //Bands are made of whole rows of tiles so no tile is split between two bands
+        let band_rows = band_height.div_ceil(options.tile_size).max(1);
+        let reference = self.reference_orbit();
+
+        let mut stats = RenderStats::default();
+        let mut first = 0;
+        while first < tiles.rows() {
+            let last = (first + band_rows).min(tiles.rows());
+            let y = first * options.tile_size;
+            let height = (last * options.tile_size).min(options.height) - y;
+            let scheduler = TileScheduler::with_tiles(
+                options.width,
+                options.height,
+                options.tile_size,
+                (first * tiles.columns()..last * tiles.columns()).collect(),
+            );
+
+            let mut pixels = vec![Pixel::default(); options.width as usize * height as usize];
+            let (rx, expected) = self.start(scheduler, &reference)?;
+            let mut received = 0;
+            for tile in rx {
+                tile.copy_into_rows(&mut pixels, options.width, y);
+                stats.add(&tile.stats);
+                received += 1;
+            }
+            missing_tiles(received, expected)?;
+            on_band(&pixels)?;
+            first = last;
+        }
+        Ok(stats)
+    }
+
+    //The reference orbit is shared by every thread so it only gets computed once
+    fn reference_orbit(&self) -> Option<Arc<ReferenceOrbit>> {
+        let options = &self.options;
+        if options.perturbation && options.formula == FormulaType::Mandelbrot {
+            Some(Arc::new(ReferenceOrbit::new(options)))
+        } else {
+            None
+        }
+    }
+
+    //Sets every worker going on the tiles from scheduler, the tiles come back on the receiver
+    //which closes once they are all done. Also gives the number of tiles to expect
+    fn start(
+        &self,
+        scheduler: TileScheduler,
+        reference: &Option<Arc<ReferenceOrbit>>,
+    ) -> Result<(Receiver<Tile>, u32), Error> {
+        let options = &self.options;
+        let expected = scheduler.remaining();
+        let scheduler = Arc::new(scheduler);
+        let (tx, rx) = mpsc::channel();
+
+        for i in 0..options.threads {
+            let mut local_options = options.clone();
+            local_options.thread_id = Some(i);
+            let local_tx = Sender::clone(&tx);
+            let scheduler_ref = Arc::clone(&scheduler);
+            match reference {
+                Some(reference) => {
+                    let reference = Arc::clone(reference);
+                    self.pool.execute(move || {
+                        perturbation(local_options, reference, local_tx, scheduler_ref)
+                    })?;
+                }
+                None => {
+                    self.pool
+                        .execute(move || mandelbrot(local_options, local_tx, scheduler_ref))?;
+                }
+            }
+        }
+
+        //Drop tx because we only need it for cloning and if we don't drop it the receiver will never close
+        drop(tx);
+        Ok((rx, expected))
+    }
+}
+
+//The channel only closes early if a worker died part way through its tiles
+fn missing_tiles(received: u32, expected: u32) -> Result<(), Error> {
+    if received < expected {
+        Err(Error::Render(format!(
+            "{} of {} tiles were never rendered, a worker thread failed",
+            expected - received,
+            expected
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+//Turns the packed colours of a finished render into an image
+pub fn to_image(pixels: &[Pixel], width: u32, height: u32) -> RgbImage {
+    let mut img: RgbImage = ImageBuffer::new(width, height);
+
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        //32 bit number but only storing rgb so split it into its 3 8 bit components
+        let colour = pixels[y as usize * width as usize + x as usize].colour;
+        let b = ((colour & 0x00ff0000) >> 16) as u8;
+        let g = ((colour & 0x0000ff00) >> 8) as u8;
+        let r = (colour & 0x000000ff) as u8;
+        *pixel = image::Rgb([r, g, b]);
+    }
+    img
+}
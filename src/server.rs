@@ -0,0 +1,355 @@
+use crate::palette::BUILTIN_PALETTES;
+use crate::pyramid::{Pyramid, TILE_SIZE};
+use crate::{Error, Options, Renderer};
+use image::png::PngEncoder;
+use image::ColorType;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+const VIEWER: &str = include_str!("viewer.html");
+
+//Requests are answered one at a time, so a client that doesn't send its request line or take its
+//response within this long is dropped rather than holding up everyone else
+const TIMEOUT: Duration = Duration::from_secs(5);
+//Longest request line read
+const MAX_REQUEST_LINE: u64 = 8192;
+
+//Deepest zoom level a tile can be asked for, well past where f64 offsets would run out
+const MAX_ZOOM: u32 = 60;
+
+//Settings a tile request can change. The image size and threads are the server's to decide and
+//anything naming a file, a palette file or an image trap, would read files off the server
+const TILE_PARAMS: [&str; 28] = [
+    "centrex",
+    "centrey",
+    "scale",
+    "rotation",
+    "iterations",
+    "max-colours",
+    "precision",
+    "simd",
+    "formula",
+    "power",
+    "phoenix-p",
+    "perturbation",
+    "series-approximation",
+    "cardioid-check",
+    "periodicity-check",
+    "samples",
+    "colour",
+    "colouring",
+    "palette",
+    "palette-offset",
+    "trap",
+    "escape-radius",
+    "lighting",
+    "light-angle",
+    "light-height",
+    "colourise",
+    "julia-re",
+    "julia-im",
+];
+
+//Limits on what a tile request can ask for so one request can't tie the server up
+const MAX_ITERATIONS: u32 = 100_000;
+const MAX_SAMPLES: u32 = 4;
+//Longest centre or Julia constant accepted and the largest power of ten it can be written with
+const MAX_DIGITS: usize = 100;
+const MAX_EXPONENT: u32 = 400;
+
+//Least recently used cache of encoded tiles
+pub struct TileCache {
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<String, (u64, Arc<Vec<u8>>)>,
+}
+
+impl TileCache {
+    pub fn new(capacity: usize) -> TileCache {
+        TileCache {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<Arc<Vec<u8>>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(used, tile)| {
+            *used = clock;
+            Arc::clone(tile)
+        })
+    }
+
+    pub fn insert(&mut self, key: String, tile: Arc<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(key, (self.clock, tile));
+    }
+}
+
+//Tile server for the bundled viewer. Answers / with the viewer and the path
+//tiles/{z}/{x}/{y}.png?key=value&... with a tile of the pyramid around base, where the query
+//can change the settings in TILE_PARAMS within the limits below.
+//Requests are answered one at a time, each tile is rendered on all of the renderer's threads
+pub struct TileServer {
+    base: Options,
+    renderer: Renderer,
+    cache: TileCache,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Arc<Vec<u8>>,
+}
+
+impl Response {
+    fn error(status: &'static str, message: String) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: Arc::new(message.into_bytes()),
+        }
+    }
+}
+
+impl TileServer {
+    //The base image size doesn't matter, every tile is rendered at TILE_SIZE
+    pub fn new(mut base: Options, cache_size: usize) -> Result<TileServer, Error> {
+        base.width = TILE_SIZE;
+        base.height = TILE_SIZE;
+        Ok(TileServer {
+            renderer: Renderer::new(base.clone())?,
+            base,
+            cache: TileCache::new(cache_size),
+        })
+    }
+
+    //Serves requests on localhost until the process is stopped
+    pub fn run(&mut self, port: u16) -> Result<(), Error> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| Error::Io(format!("Could not listen on port {}", port), e))?;
+        //A client that goes away early is no reason to stop serving
+        for stream in listener.incoming().flatten() {
+            let _ = self.handle(stream);
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut request = String::new();
+        BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request)?;
+        let mut parts = request.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            _ if request.len() as u64 == MAX_REQUEST_LINE && !request.ends_with('\n') => {
+                Response::error("400 Bad Request", String::from("Request line too long"))
+            }
+            (Some("GET"), Some(target)) => self.respond(target),
+            _ => Response::error("400 Bad Request", String::from("Only GET is supported")),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()
+    }
+
+    fn respond(&mut self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        if path == "/" || path == "/index.html" {
+            let palettes: String = BUILTIN_PALETTES
+                .iter()
+                .map(|name| format!("<option>{}</option>", name))
+                .collect();
+            return Response {
+                status: "200 OK",
+                content_type: "text/html; charset=utf-8",
+                body: Arc::new(VIEWER.replace("<!--palettes-->", &palettes).into_bytes()),
+            };
+        }
+
+        let tile = path
+            .strip_prefix("/tiles/")
+            .and_then(|rest| rest.strip_suffix(".png"))
+            .map(|rest| rest.split('/').map(str::parse).collect::<Vec<_>>());
+        match tile.as_deref() {
+            Some([Ok(z), Ok(x), Ok(y)]) => match self.tile(*z, *x, *y, query) {
+                Some(png) => Response {
+                    status: "200 OK",
+                    content_type: "image/png",
+                    body: png,
+                },
+                //The reason stays on the server, errors can quote whatever was read
+                None => Response::error("400 Bad Request", String::from("Bad tile request")),
+            },
+            _ => Response::error("404 Not Found", format!("Nothing at {}", path)),
+        }
+    }
+
+    fn tile(&mut self, zoom: u32, x: u32, y: u32, query: &str) -> Option<Arc<Vec<u8>>> {
+        if zoom > MAX_ZOOM || x as u64 >> zoom != 0 || y as u64 >> zoom != 0 {
+            return None;
+        }
+
+        let mut options = self.base.clone();
+        let mut params = Vec::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (key, value) = (decode(key)?, decode(value)?);
+            //Empty values keep the server's setting, the viewer sends them for unset fields
+            if !value.is_empty() {
+                if !allowed(&key, &value) {
+                    return None;
+                }
+                options.set_param(&key, &value).ok()?;
+                params.push((key, value));
+            }
+        }
+        if options.max_iter > MAX_ITERATIONS || options.samples > MAX_SAMPLES {
+            return None;
+        }
+        //The same settings in any order make the same tile
+        params.sort();
+        let key = format!("{}/{}/{}?{:?}", zoom, x, y, params);
+        if let Some(png) = self.cache.get(&key) {
+            return Some(png);
+        }
+
+        let pyramid = Pyramid { options, levels: 1 };
+        //Settings from the query that can't make an image are the client's mistake
+        self.renderer
+            .set_options(pyramid.tile_options(zoom, x, y))
+            .ok()?;
+        let image = self.renderer.render().ok()?.image;
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .encode(&image, image.width(), image.height(), ColorType::Rgb8)
+            .ok()?;
+
+        let png = Arc::new(png);
+        self.cache.insert(key, Arc::clone(&png));
+        Some(png)
+    }
+}
+
+//Whether a tile request may set key to value, checked before the value is parsed
+fn allowed(key: &str, value: &str) -> bool {
+    match key {
+        _ if !TILE_PARAMS.contains(&key) => false,
+        "palette" => BUILTIN_PALETTES.contains(&value),
+        "trap" => !value.starts_with("image"),
+        "scale" => value
+            .parse::<f64>()
+            .is_ok_and(|scale| scale.is_finite() && scale > 0.0),
+        "centrex" | "centrey" | "julia-re" | "julia-im" => {
+            let (digits, exponent) = value.split_once(['e', 'E']).unwrap_or((value, "0"));
+            let exponent = exponent.parse::<i32>().map_or(u32::MAX, i32::unsigned_abs);
+            digits.len() <= MAX_DIGITS && exponent <= MAX_EXPONENT
+        }
+        _ => true,
+    }
+}
+
+//Undoes URL encoding, %xx escapes and + for space
+fn decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text.get(i + 1..i + 3)?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BigFixed;
+
+    //Settings the way serve builds them, with no image size of their own
+    fn base() -> Options {
+        Options::new(
+            256,
+            64,
+            0,
+            0,
+            BigFixed::from(-0.75),
+            BigFixed::from(0.0),
+            2.5,
+            1,
+            7,
+            false,
+            1,
+            false,
+        )
+    }
+
+    #[test]
+    fn serves_tiles_from_sizeless_base() {
+        let mut server = TileServer::new(base(), 4).unwrap();
+        let response = server.respond("/tiles/0/0/0.png");
+        assert_eq!(response.status, "200 OK");
+        assert!(response.body.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn rejects_files_and_oversized_settings() {
+        let mut server = TileServer::new(base(), 4).unwrap();
+        for query in [
+            "palette=/etc/passwd",
+            "trap=image:0,0,2,/etc/passwd",
+            "width=10",
+            "iterations=1000000",
+            "samples=100",
+            "centrex=1e-100000",
+            "scale=0",
+            "scale=-2",
+            "scale=NaN",
+            "scale=inf",
+        ] {
+            let response = server.respond(&format!("/tiles/0/0/0.png?{}", query));
+            assert_eq!(response.status, "400 Bad Request", "{}", query);
+            assert_eq!(&response.body[..], b"Bad tile request");
+        }
+        let response = server.respond("/tiles/0/0/0.png?palette=fire&iterations=100");
+        assert_eq!(response.status, "200 OK");
+    }
+}
@@ -0,0 +1,355 @@
+use crate::formula::Mandelbrot;
+use crate::precision::{Precision, Real};
+use crate::{
+    escape_time, render_tiles_batched, Formula, Options, Sample, Tile, TileScheduler, Trackers,
+};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+//Whether the vectorised Mandelbrot kernel can run for this precision on this cpu
+pub fn supported(precision: Precision) -> bool {
+    match precision {
+        Precision::Single | Precision::Double => has_avx(),
+        _ => false,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn has_avx() -> bool {
+    is_x86_feature_detected!("avx")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn has_avx() -> bool {
+    false
+}
+
+//Hardware float types with a kernel that iterates LANES samples at once. Each lane does exactly
+//the same operations in the same order as escape_time with the Mandelbrot formula, so the
+//results are bit for bit the same as the scalar path. Returns how many lanes were stopped by
+//periodicity checking
+trait Lanes: Real + Copy {
+    const LANES: usize;
+
+    //Safety: the cpu must support avx and every slice must hold at least LANES values
+    unsafe fn escape_time_lanes(
+        x: &[Self],
+        y: &[Self],
+        cx: &[Self],
+        cy: &[Self],
+        limits: (u32, f64, bool),
+        out: &mut [(u32, f64)],
+    ) -> u64;
+}
+
+//Writes the result for every lane set in mask
+#[inline]
+fn record<T: Copy + Into<f64>>(out: &mut [(u32, f64)], mask: i32, iter: u32, norms: &[T]) -> u64 {
+    let mut count = 0;
+    for (lane, result) in out.iter_mut().enumerate().take(norms.len()) {
+        if mask & (1 << lane) != 0 {
+            *result = (iter, norms[lane].into());
+            count += 1;
+        }
+    }
+    count
+}
+
+impl Lanes for f64 {
+    const LANES: usize = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    unsafe fn escape_time_lanes(
+        x: &[f64],
+        y: &[f64],
+        cx: &[f64],
+        cy: &[f64],
+        limits: (u32, f64, bool),
+        out: &mut [(u32, f64)],
+    ) -> u64 {
+        let (max_iter, bailout, periodicity) = limits;
+        let mut zx = _mm256_loadu_pd(x.as_ptr());
+        let mut zy = _mm256_loadu_pd(y.as_ptr());
+        let cx = _mm256_loadu_pd(cx.as_ptr());
+        let cy = _mm256_loadu_pd(cy.as_ptr());
+        let limit = _mm256_set1_pd(bailout);
+        let mut norm = _mm256_setzero_pd();
+        let mut norms = [0.0f64; 4];
+        let mut active = 0b1111;
+        let mut iter: u32 = 0;
+        let (mut savedx, mut savedy) = (zx, zy);
+        let mut save_at: u32 = 1;
+        let mut cycles = 0;
+
+        while iter <= max_iter {
+            let x2 = _mm256_mul_pd(zx, zx);
+            let y2 = _mm256_mul_pd(zy, zy);
+            norm = _mm256_add_pd(x2, y2);
+            //Lanes that escape are recorded and masked off, the loop ends once all have
+            let escaped = _mm256_movemask_pd(_mm256_cmp_pd(norm, limit, _CMP_GE_OQ)) & active;
+            if escaped != 0 {
+                _mm256_storeu_pd(norms.as_mut_ptr(), norm);
+                record(out, escaped, iter, &norms);
+                active &= !escaped;
+                if active == 0 {
+                    return cycles;
+                }
+            }
+            let xy = _mm256_mul_pd(zx, zy);
+            zx = _mm256_add_pd(_mm256_sub_pd(x2, y2), cx);
+            zy = _mm256_add_pd(_mm256_add_pd(xy, xy), cy);
+            iter += 1;
+
+            if periodicity {
+                let same = _mm256_and_pd(
+                    _mm256_cmp_pd(zx, savedx, _CMP_EQ_OQ),
+                    _mm256_cmp_pd(zy, savedy, _CMP_EQ_OQ),
+                );
+                let repeated = _mm256_movemask_pd(same) & active;
+                if repeated != 0 {
+                    _mm256_storeu_pd(norms.as_mut_ptr(), norm);
+                    cycles += record(out, repeated, max_iter + 1, &norms);
+                    active &= !repeated;
+                    if active == 0 {
+                        return cycles;
+                    }
+                }
+                if iter == save_at {
+                    savedx = zx;
+                    savedy = zy;
+                    save_at = save_at.saturating_mul(2);
+                }
+            }
+        }
+
+        _mm256_storeu_pd(norms.as_mut_ptr(), norm);
+        record(out, active, iter, &norms);
+        cycles
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    unsafe fn escape_time_lanes(
+        x: &[f64],
+        y: &[f64],
+        cx: &[f64],
+        cy: &[f64],
+        limits: (u32, f64, bool),
+        out: &mut [(u32, f64)],
+    ) -> u64 {
+        escape_time_scalar(x, y, cx, cy, limits, &mut out[..Self::LANES])
+    }
+}
+
+impl Lanes for f32 {
+    const LANES: usize = 8;
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    unsafe fn escape_time_lanes(
+        x: &[f32],
+        y: &[f32],
+        cx: &[f32],
+        cy: &[f32],
+        limits: (u32, f64, bool),
+        out: &mut [(u32, f64)],
+    ) -> u64 {
+        //The scalar path compares the f32 norm widened to f64, which is the same as comparing
+        //against the smallest f32 that is not below the bailout
+        let (max_iter, bailout, periodicity) = limits;
+        let mut limit32 = bailout as f32;
+        if (limit32 as f64) < bailout {
+            limit32 = f32::from_bits(limit32.to_bits() + 1);
+        }
+
+        let mut zx = _mm256_loadu_ps(x.as_ptr());
+        let mut zy = _mm256_loadu_ps(y.as_ptr());
+        let cx = _mm256_loadu_ps(cx.as_ptr());
+        let cy = _mm256_loadu_ps(cy.as_ptr());
+        let limit = _mm256_set1_ps(limit32);
+        let mut norm = _mm256_setzero_ps();
+        let mut norms = [0.0f32; 8];
+        let mut active = 0b1111_1111;
+        let mut iter: u32 = 0;
+        let (mut savedx, mut savedy) = (zx, zy);
+        let mut save_at: u32 = 1;
+        let mut cycles = 0;
+
+        while iter <= max_iter {
+            let x2 = _mm256_mul_ps(zx, zx);
+            let y2 = _mm256_mul_ps(zy, zy);
+            norm = _mm256_add_ps(x2, y2);
+            let escaped = _mm256_movemask_ps(_mm256_cmp_ps(norm, limit, _CMP_GE_OQ)) & active;
+            if escaped != 0 {
+                _mm256_storeu_ps(norms.as_mut_ptr(), norm);
+                record(out, escaped, iter, &norms);
+                active &= !escaped;
+                if active == 0 {
+                    return cycles;
+                }
+            }
+            let xy = _mm256_mul_ps(zx, zy);
+            zx = _mm256_add_ps(_mm256_sub_ps(x2, y2), cx);
+            zy = _mm256_add_ps(_mm256_add_ps(xy, xy), cy);
+            iter += 1;
+
+            if periodicity {
+                let same = _mm256_and_ps(
+                    _mm256_cmp_ps(zx, savedx, _CMP_EQ_OQ),
+                    _mm256_cmp_ps(zy, savedy, _CMP_EQ_OQ),
+                );
+                let repeated = _mm256_movemask_ps(same) & active;
+                if repeated != 0 {
+                    _mm256_storeu_ps(norms.as_mut_ptr(), norm);
+                    cycles += record(out, repeated, max_iter + 1, &norms);
+                    active &= !repeated;
+                    if active == 0 {
+                        return cycles;
+                    }
+                }
+                if iter == save_at {
+                    savedx = zx;
+                    savedy = zy;
+                    save_at = save_at.saturating_mul(2);
+                }
+            }
+        }
+
+        _mm256_storeu_ps(norms.as_mut_ptr(), norm);
+        record(out, active, iter, &norms);
+        cycles
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    unsafe fn escape_time_lanes(
+        x: &[f32],
+        y: &[f32],
+        cx: &[f32],
+        cy: &[f32],
+        limits: (u32, f64, bool),
+        out: &mut [(u32, f64)],
+    ) -> u64 {
+        escape_time_scalar(x, y, cx, cy, limits, &mut out[..Self::LANES])
+    }
+}
+
+//Scalar fallback for samples that don't fill a whole vector
+fn escape_time_scalar<R: Real + Copy>(
+    x: &[R],
+    y: &[R],
+    cx: &[R],
+    cy: &[R],
+    limits: (u32, f64, bool),
+    out: &mut [(u32, f64)],
+) -> u64 {
+    let (max_iter, bailout, periodicity) = limits;
+    let zero = R::from_f64(0.0, 0);
+    let mut cycles = 0;
+    for (i, result) in out.iter_mut().enumerate() {
+        *result = escape_time(
+            &Mandelbrot,
+            (x[i], y[i]),
+            &(cx[i], cy[i]),
+            &zero,
+            (max_iter, bailout),
+            if periodicity { Some(&mut cycles) } else { None },
+            &mut Trackers::default(),
+        );
+    }
+    cycles
+}
+
+//Vectorised worker for the Mandelbrot formula, a drop in for mandelbrot when supported is true
+pub(crate) fn mandelbrot(
+    options: &Options,
+    precision: Precision,
+    bits: u32,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+) {
+    match precision {
+        Precision::Single => mandelbrot_with::<f32>(options, bits, sender, scheduler),
+        _ => mandelbrot_with::<f64>(options, bits, sender, scheduler),
+    }
+}
+
+fn mandelbrot_with<R: Lanes>(
+    options: &Options,
+    bits: u32,
+    sender: Sender<Tile>,
+    scheduler: Arc<TileScheduler>,
+) {
+    let bailout = options.escape_radius * options.escape_radius;
+    let centrex = R::from_fixed(&options.centrex, bits);
+    let centrey = R::from_fixed(&options.centrey, bits);
+    let julia = options
+        .julia
+        .as_ref()
+        .map(|(re, im)| (R::from_fixed(re, bits), R::from_fixed(im, bits)));
+
+    let limits = (options.max_iter, bailout, options.periodicity_check);
+    let cardioid_check = options.cardioid_check && julia.is_none();
+
+    //Samples that still need iterating are packed together so no lane is wasted on points the
+    //cardioid test already settled, index says where each one goes back to
+    let mut index: Vec<usize> = Vec::new();
+    let mut x: Vec<R> = Vec::new();
+    let mut y: Vec<R> = Vec::new();
+    let mut cx: Vec<R> = Vec::new();
+    let mut cy: Vec<R> = Vec::new();
+    let mut packed: Vec<(u32, f64)> = Vec::new();
+    render_tiles_batched(options, sender, scheduler, |offsets, results, stats| {
+        index.clear();
+        x.clear();
+        y.clear();
+        cx.clear();
+        cy.clear();
+        for (i, &(offsetx, offsety)) in offsets.iter().enumerate() {
+            let x0 = centrex + R::from_f64(offsetx, bits);
+            let y0 = centrey + R::from_f64(offsety, bits);
+            if cardioid_check && Mandelbrot.known_interior(&(x0, y0)) {
+                stats.cardioid += 1;
+                results[i] = Sample::new(options.max_iter + 1, 0.0);
+                continue;
+            }
+            index.push(i);
+            x.push(x0);
+            y.push(y0);
+            //Julia sets start at the pixel and add the same constant every time
+            let (re, im) = julia.unwrap_or((x0, y0));
+            cx.push(re);
+            cy.push(im);
+        }
+
+        packed.resize(index.len(), (0, 0.0));
+        let whole = index.len() / R::LANES * R::LANES;
+        for start in (0..whole).step_by(R::LANES) {
+            //Safe because supported checked for avx and start + LANES is within the slices
+            stats.periodicity += unsafe {
+                R::escape_time_lanes(
+                    &x[start..],
+                    &y[start..],
+                    &cx[start..],
+                    &cy[start..],
+                    limits,
+                    &mut packed[start..start + R::LANES],
+                )
+            };
+        }
+        stats.periodicity += escape_time_scalar(
+            &x[whole..],
+            &y[whole..],
+            &cx[whole..],
+            &cy[whole..],
+            limits,
+            &mut packed[whole..],
+        );
+
+        for (&i, &(iter, norm)) in index.iter().zip(packed.iter()) {
+            results[i] = Sample::new(iter, norm);
+        }
+    });
+}
@@ -0,0 +1,171 @@
+use crate::animated::write_chunk;
+use crate::metadata::write_text;
+use crate::{Error, Pixel};
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+//Compressed image data is split into IDAT chunks of this size
+const IDAT_SIZE: usize = 1 << 20;
+
+//Collects the compressed stream into IDAT chunks
+struct IdatWriter {
+    out: BufWriter<File>,
+    buffer: Vec<u8>,
+}
+
+impl Write for IdatWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = buf.len().min(IDAT_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..written]);
+        if self.buffer.len() == IDAT_SIZE {
+            self.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.out, b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+//PNG written a few rows at a time, for images too big to hold in memory. Each row is Paeth
+//filtered against the one above, so only the last row is kept
+pub struct PngStream {
+    path: String,
+    encoder: ZlibEncoder<IdatWriter>,
+    width: u32,
+    rows_left: u32,
+    previous: Vec<u8>,
+    row: Vec<u8>,
+}
+
+impl PngStream {
+    //text is written as tEXt chunks ahead of the image, see metadata::text_chunks
+    pub fn create(
+        path: &str,
+        width: u32,
+        height: u32,
+        text: &[(String, String)],
+    ) -> Result<PngStream, Error> {
+        let png = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        if !png {
+            return Err(Error::Options(format!(
+                "{}: streamed images can only be written as .png",
+                path
+            )));
+        }
+        let error = |e: std::io::Error| Error::io(path, e);
+        let mut out = BufWriter::new(File::create(path).map_err(error)?);
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        //8 bit RGB, deflate, adaptive filtering, not interlaced
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        out.write_all(b"\x89PNG\r\n\x1a\n").map_err(error)?;
+        write_chunk(&mut out, b"IHDR", &header).map_err(error)?;
+        for (keyword, text) in text {
+            write_text(&mut out, keyword, text).map_err(error)?;
+        }
+
+        let idat = IdatWriter {
+            out,
+            buffer: Vec::with_capacity(IDAT_SIZE),
+        };
+        Ok(PngStream {
+            path: String::from(path),
+            encoder: ZlibEncoder::new(idat, Compression::Default),
+            width,
+            rows_left: height,
+            previous: vec![0; width as usize * 3],
+            row: Vec::with_capacity(width as usize * 3 + 1),
+        })
+    }
+
+    //Adds the next whole rows of the image, pixels are in row order
+    pub fn write_rows(&mut self, pixels: &[Pixel]) -> Result<(), Error> {
+        let width = self.width.max(1) as usize;
+        let rows = pixels.len() / width;
+        if !pixels.len().is_multiple_of(width) || rows as u32 > self.rows_left {
+            return Err(Error::Options(format!(
+                "{}: rows don't fit the image",
+                self.path
+            )));
+        }
+
+        for line in pixels.chunks(width) {
+            let current: Vec<u8> = line
+                .iter()
+                .flat_map(|pixel| {
+                    let colour = pixel.colour;
+                    [
+                        (colour & 0x000000ff) as u8,
+                        ((colour & 0x0000ff00) >> 8) as u8,
+                        ((colour & 0x00ff0000) >> 16) as u8,
+                    ]
+                })
+                .collect();
+
+            //Filter type 4 is Paeth, each byte is stored as the difference from a guess made
+            //from the pixels left, above and above left of it
+            self.row.clear();
+            self.row.push(4);
+            for i in 0..current.len() {
+                let (left, up_left) = if i >= 3 {
+                    (current[i - 3], self.previous[i - 3])
+                } else {
+                    (0, 0)
+                };
+                let guess = paeth(left, self.previous[i], up_left);
+                self.row.push(current[i].wrapping_sub(guess));
+            }
+            self.previous = current;
+
+            self.encoder
+                .write_all(&self.row)
+                .map_err(|e| Error::io(&self.path, e))?;
+        }
+        self.rows_left -= rows as u32;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        let path = self.path;
+        let error = |e: std::io::Error| Error::io(&path, e);
+        if self.rows_left > 0 {
+            return Err(Error::Options(format!(
+                "{}: {} rows never written",
+                path, self.rows_left
+            )));
+        }
+        let mut idat = self.encoder.finish().map_err(error)?;
+        idat.flush().map_err(error)?;
+        write_chunk(&mut idat.out, b"IEND", &[]).map_err(error)?;
+        idat.out.flush().map_err(error)
+    }
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{Pixel, PixelExtras};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+//Square region of the image, the unit of work handed to a worker
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileRect {
+    pub index: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+//Samples the interior tests settled without iterating to max_iter
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    //Inside the main cardioid or the period 2 bulb
+    pub cardioid: u64,
+    //Orbit came back to a point it had already visited
+    pub periodicity: u64,
+}
+
+impl RenderStats {
+    pub fn add(&mut self, other: &RenderStats) {
+        self.cardioid += other.cardioid;
+        self.periodicity += other.periodicity;
+    }
+}
+
+//Finished tile sent back from a worker in one go, pixels are in row order. extras is in the
+//same order when the options use them and empty otherwise
+#[derive(Clone, Debug)]
+pub struct Tile {
+    pub rect: TileRect,
+    pub pixels: Vec<Pixel>,
+    pub extras: Vec<PixelExtras>,
+    pub thread_id: u32,
+    pub stats: RenderStats,
+}
+
+impl Tile {
+    //Copy the tile into full image sized buffers. The extras are left out when either side
+    //has none
+    pub fn copy_into(&self, out: &mut [Pixel], extras: &mut [PixelExtras], image_width: u32) {
+        self.copy_into_rows(out, image_width, 0);
+        if !self.extras.is_empty() && !extras.is_empty() {
+            copy_rows(self.rect, &self.extras, extras, image_width, 0);
+        }
+    }
+
+    //Copy the tile's pixels into a buffer holding the image rows from first_row down
+    pub fn copy_into_rows(&self, out: &mut [Pixel], image_width: u32, first_row: u32) {
+        copy_rows(self.rect, &self.pixels, out, image_width, first_row);
+    }
+}
+
+fn copy_rows<T: Copy>(rect: TileRect, from: &[T], out: &mut [T], image_width: u32, first_row: u32) {
+    for row in 0..rect.height {
+        let y = (rect.y + row - first_row) as usize;
+        let start = y * image_width as usize + rect.x as usize;
+        let from = &from[(row * rect.width) as usize..][..rect.width as usize];
+        out[start..start + rect.width as usize].copy_from_slice(from);
+    }
+}
+
+//Hands tiles out to workers. Claiming is a single atomic add so workers never wait on each other
+#[derive(Debug)]
+pub struct TileScheduler {
+    next: AtomicU32,
+    //Only these tiles get handed out when rendering part of the image
+    pending: Option<Vec<u32>>,
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    columns: u32,
+    rows: u32,
+}
+
+impl TileScheduler {
+    pub fn new(width: u32, height: u32, tile_size: u32) -> TileScheduler {
+        TileScheduler {
+            next: AtomicU32::new(0),
+            pending: None,
+            width,
+            height,
+            tile_size,
+            columns: width.div_ceil(tile_size),
+            rows: height.div_ceil(tile_size),
+        }
+    }
+
+    //Scheduler that only hands out the tiles listed, in that order
+    pub fn with_tiles(width: u32, height: u32, tile_size: u32, tiles: Vec<u32>) -> TileScheduler {
+        let mut scheduler = TileScheduler::new(width, height, tile_size);
+        scheduler.pending = Some(tiles);
+        scheduler
+    }
+
+    //Scheduler that skips the tiles in done
+    pub fn with_done(width: u32, height: u32, tile_size: u32, done: &[u32]) -> TileScheduler {
+        let len = TileScheduler::new(width, height, tile_size).len();
+        let mut finished = vec![false; len as usize];
+        for &index in done {
+            finished[index as usize] = true;
+        }
+        let pending = (0..len).filter(|&i| !finished[i as usize]).collect();
+        TileScheduler::with_tiles(width, height, tile_size, pending)
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    //Number of tiles that will be handed out
+    pub fn remaining(&self) -> u32 {
+        match &self.pending {
+            Some(pending) => pending.len() as u32,
+            None => self.len(),
+        }
+    }
+
+    //Total number of tiles in the image, including any already done
+    pub fn len(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn rect(&self, index: u32) -> TileRect {
+        let x = (index % self.columns) * self.tile_size;
+        let y = (index / self.columns) * self.tile_size;
+        TileRect {
+            index,
+            x,
+            y,
+            width: self.tile_size.min(self.width - x),
+            height: self.tile_size.min(self.height - y),
+        }
+    }
+
+    //Next tile nobody has started yet, None once the image is done
+    pub fn claim(&self) -> Option<TileRect> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let index = match &self.pending {
+            Some(pending) => *pending.get(next as usize)?,
+            None if next < self.len() => next,
+            None => return None,
+        };
+        Some(self.rect(index))
+    }
+}
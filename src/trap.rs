@@ -0,0 +1,163 @@
+use image::RgbImage;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+//Shape the orbit is measured against for trap colouring. Positions and sizes are in the complex
+//plane. Written as kind:numbers, the numbers can be left off for the defaults:
+//
+//  point:X,Y              (0,0)
+//  line:X,Y,ANGLE         through X,Y at ANGLE degrees (0,0,0)
+//  cross:X,Y              horizontal and vertical lines through X,Y (0,0)
+//  circle:X,Y,RADIUS      (0,0,1)
+//  image:X,Y,SIZE,FILE    bitmap on the square SIZE across centred on X,Y (0,0,2)
+#[derive(Clone, Debug)]
+pub enum Trap {
+    Point(f64, f64),
+    Line(f64, f64, f64),
+    Cross(f64, f64),
+    Circle(f64, f64, f64),
+    Image(TrapImage),
+}
+
+#[derive(Clone, Debug)]
+pub struct TrapImage {
+    pub path: String,
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+    pub image: Arc<RgbImage>,
+}
+
+impl Trap {
+    pub fn tracker(&self) -> TrapTracker<'_> {
+        TrapTracker {
+            trap: self,
+            distance: f64::INFINITY,
+            texel: None,
+        }
+    }
+}
+
+//Follows one orbit, keeping the closest it came to the trap. Image traps keep the colour under
+//the first point that landed on the image instead
+pub struct TrapTracker<'a> {
+    trap: &'a Trap,
+    pub distance: f64,
+    pub texel: Option<[f64; 3]>,
+}
+
+impl TrapTracker<'_> {
+    #[inline]
+    pub fn visit(&mut self, x: f64, y: f64) {
+        let distance = match self.trap {
+            Trap::Point(px, py) => (x - px).hypot(y - py),
+            Trap::Line(px, py, angle) => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                ((x - px) * sin - (y - py) * cos).abs()
+            }
+            Trap::Cross(px, py) => (x - px).abs().min((y - py).abs()),
+            Trap::Circle(px, py, radius) => ((x - px).hypot(y - py) - radius).abs(),
+            Trap::Image(trap) => {
+                if self.texel.is_some() {
+                    return;
+                }
+                //Image rows follow the imaginary axis the same way as the render
+                let u = (x - trap.x) / trap.size + 0.5;
+                let v = (y - trap.y) / trap.size + 0.5;
+                if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                    return;
+                }
+                let image = &trap.image;
+                let pixel = image.get_pixel(
+                    (u * image.width() as f64) as u32,
+                    (v * image.height() as f64) as u32,
+                );
+                self.texel = Some([pixel[0] as f64, pixel[1] as f64, pixel[2] as f64]);
+                0.0
+            }
+        };
+        self.distance = self.distance.min(distance);
+    }
+}
+
+impl FromStr for Trap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Trap, String> {
+        let bad = || format!("Could not parse trap {}", s);
+        let (kind, rest) = s.split_once(':').unwrap_or((s, ""));
+        let mut fields: Vec<&str> = rest.split(',').filter(|n| !n.is_empty()).collect();
+        //Image paths can hold commas so they get everything after the numbers
+        let mut path = "";
+        if kind == "image" {
+            let parts: Vec<&str> = rest.splitn(4, ',').collect();
+            if parts.len() == 4 && parts[..3].iter().all(|n| n.parse::<f64>().is_ok()) {
+                path = parts[3];
+                fields = parts[..3].to_vec();
+            } else {
+                path = rest;
+                fields.clear();
+            }
+        }
+        let numbers = fields
+            .iter()
+            .map(|n| n.parse::<f64>().map_err(|_| bad()))
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        let with_defaults = |defaults: &[f64]| -> Result<Vec<f64>, String> {
+            if numbers.is_empty() {
+                Ok(defaults.to_vec())
+            } else if numbers.len() == defaults.len() {
+                Ok(numbers.clone())
+            } else {
+                Err(bad())
+            }
+        };
+        let trap = match kind {
+            "point" => {
+                let n = with_defaults(&[0.0, 0.0])?;
+                Trap::Point(n[0], n[1])
+            }
+            "line" => {
+                let n = with_defaults(&[0.0, 0.0, 0.0])?;
+                Trap::Line(n[0], n[1], n[2])
+            }
+            "cross" => {
+                let n = with_defaults(&[0.0, 0.0])?;
+                Trap::Cross(n[0], n[1])
+            }
+            "circle" => {
+                let n = with_defaults(&[0.0, 0.0, 1.0])?;
+                Trap::Circle(n[0], n[1], n[2])
+            }
+            "image" if !path.is_empty() => {
+                let n = with_defaults(&[0.0, 0.0, 2.0])?;
+                let image = image::open(path).map_err(|e| format!("{}: {}", path, e))?;
+                Trap::Image(TrapImage {
+                    path: String::from(path),
+                    x: n[0],
+                    y: n[1],
+                    size: n[2],
+                    image: Arc::new(image.to_rgb8()),
+                })
+            }
+            _ => return Err(bad()),
+        };
+        Ok(trap)
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::Point(x, y) => write!(f, "point:{},{}", x, y),
+            Trap::Line(x, y, angle) => write!(f, "line:{},{},{}", x, y, angle),
+            Trap::Cross(x, y) => write!(f, "cross:{},{}", x, y),
+            Trap::Circle(x, y, radius) => write!(f, "circle:{},{},{}", x, y, radius),
+            Trap::Image(trap) => {
+                write!(f, "image:{},{},{},{}", trap.x, trap.y, trap.size, trap.path)
+            }
+        }
+    }
+}